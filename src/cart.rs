@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+
+use bson::Decimal128;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    decimal::{self, Decimal},
+    money::Currency,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CartLine {
+    pub line_id: String,
+    pub product_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sku: Option<String>,
+    pub unit_price: Decimal128,
+    pub quantity: i32,
+    #[serde(default)]
+    pub categories: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl CartLine {
+    pub fn new(
+        line_id: impl Into<String>,
+        product_id: impl Into<String>,
+        unit_price: Decimal128,
+        quantity: i32,
+    ) -> Self {
+        Self {
+            line_id: line_id.into(),
+            product_id: product_id.into(),
+            variant_id: None,
+            sku: None,
+            unit_price,
+            quantity,
+            categories: Vec::new(),
+            tags: Vec::new(),
+        }
+    }
+
+    pub fn with_variant(mut self, variant_id: impl Into<String>) -> Self {
+        self.variant_id = Some(variant_id.into());
+        self
+    }
+
+    pub fn with_sku(mut self, sku: impl Into<String>) -> Self {
+        self.sku = Some(sku.into());
+        self
+    }
+
+    pub fn with_categories(mut self, categories: Vec<String>) -> Self {
+        self.categories = categories;
+        self
+    }
+
+    pub fn with_tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    /// Unit price in exact decimal form; unusable prices count as zero.
+    pub fn price(&self) -> Decimal {
+        decimal::from_bson_or_zero(self.unit_price)
+    }
+
+    pub fn subtotal(&self) -> Decimal {
+        self.price() * Decimal::from(self.quantity.max(0))
+    }
+
+    pub fn in_any_category(&self, category_ids: &[String]) -> bool {
+        category_ids.iter().any(|c| self.categories.contains(c))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cart {
+    pub shop_id: String,
+    pub lines: Vec<CartLine>,
+    pub shipping_cost: Decimal128,
+    /// Currency of every price in the cart.
+    #[serde(default)]
+    pub currency: Currency,
+}
+
+impl Cart {
+    pub fn new(
+        shop_id: impl Into<String>,
+        lines: Vec<CartLine>,
+        shipping_cost: Decimal128,
+    ) -> Self {
+        Self {
+            shop_id: shop_id.into(),
+            lines,
+            shipping_cost,
+            currency: Currency::default(),
+        }
+    }
+
+    pub fn with_currency(mut self, currency: Currency) -> Self {
+        self.currency = currency;
+        self
+    }
+
+    /// Sum of every line subtotal, excluding shipping.
+    pub fn subtotal(&self) -> Decimal {
+        self.lines.iter().map(CartLine::subtotal).sum()
+    }
+
+    /// Units of `product_id` across all lines, whatever their variant. The
+    /// count saturates rather than overflowing.
+    pub fn quantity_of(&self, product_id: &str) -> i32 {
+        self.lines
+            .iter()
+            .filter(|l| l.product_id == product_id)
+            .fold(0, |total, l| total.saturating_add(l.quantity))
+    }
+
+    pub fn product_quantities(&self) -> HashMap<String, i32> {
+        let mut quantities = HashMap::new();
+        for line in &self.lines {
+            let total: &mut i32 = quantities.entry(line.product_id.clone()).or_insert(0);
+            *total = total.saturating_add(line.quantity);
+        }
+        quantities
+    }
+
+    pub fn product_categories(&self) -> HashMap<String, Vec<String>> {
+        let mut categories: HashMap<String, Vec<String>> = HashMap::new();
+        for line in &self.lines {
+            let entry = categories.entry(line.product_id.clone()).or_default();
+            for category in &line.categories {
+                if !entry.contains(category) {
+                    entry.push(category.clone());
+                }
+            }
+        }
+        categories
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{cart, d128, dec, line};
+
+    #[test]
+    fn test_subtotals_ignore_negative_quantities_and_bad_prices() {
+        let mut unpriced = line("c", "p-3", "1", 4);
+        unpriced.unit_price = Decimal128::from_bytes([0x7c; 16]);
+        let cart = cart(vec![
+            line("a", "p-1", "2.50", 3),
+            line("b", "p-2", "10", -2),
+            unpriced,
+        ]);
+        assert_eq!(cart.subtotal(), dec("7.50"));
+        assert_eq!(cart.shipping_cost, d128("5"));
+    }
+
+    #[test]
+    fn test_quantities_and_categories_are_grouped_by_product() {
+        let cart = cart(vec![
+            line("a", "p-1", "5", 2).with_categories(vec!["shoes".to_string()]),
+            line("b", "p-1", "5", 3)
+                .with_variant("red")
+                .with_categories(vec!["shoes".to_string(), "sale".to_string()]),
+            line("c", "p-2", "5", 1),
+        ]);
+        assert_eq!(cart.quantity_of("p-1"), 5);
+        assert_eq!(cart.product_quantities()["p-2"], 1);
+        assert_eq!(cart.product_categories()["p-1"], ["shoes", "sale"]);
+        assert!(cart.lines[1].in_any_category(&["sale".to_string()]));
+        assert!(!cart.lines[0].in_any_category(&["sale".to_string()]));
+    }
+
+    #[test]
+    fn test_quantities_saturate_instead_of_overflowing() {
+        let cart = cart(vec![
+            line("a", "p-1", "1", i32::MAX),
+            line("b", "p-1", "1", i32::MAX),
+        ]);
+        assert_eq!(cart.quantity_of("p-1"), i32::MAX);
+        assert_eq!(cart.product_quantities()["p-1"], i32::MAX);
+    }
+}
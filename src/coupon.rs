@@ -1,6 +1,14 @@
-use crate::datetime::datetime_serialization;
+use crate::{
+    cart::{Cart, CartLine},
+    coupon_code::CodeTemplate,
+    datetime::datetime_serialization,
+    decimal::{self, Decimal},
+    discount::EvaluationContext,
+    error::{Entity, FieldError, ServiceError},
+    membership::MembershipTier,
+    money::{Currency, Money},
+};
 use chrono::{DateTime, Utc};
-use mongodb::bson::{Decimal128, doc};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -9,45 +17,599 @@ pub struct Coupon {
     pub shop_id: String,
     pub code: String,
     pub description: Option<String>,
+    /// Campaign a generated coupon belongs to; coupons from one batch share
+    /// it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub campaign_id: Option<String>,
     pub is_active: bool,
     pub discount_type: CouponDiscountType,
-    pub discount_value: Decimal128,
+    /// The amount off, or for percentage coupons the percentage. Either way
+    /// its currency is the coupon's, which the cart must be priced in.
+    pub discount_value: Money,
     pub is_single_use: bool,
     pub used_count: i32,
     pub max_uses: Option<i32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    /// How many times one customer may redeem the coupon.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_uses_per_customer: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     #[serde(with = "datetime_serialization")]
     pub starts_at: Option<DateTime<Utc>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     #[serde(with = "datetime_serialization")]
     pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "CouponRestrictions::is_empty")]
+    pub restrictions: CouponRestrictions,
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
     pub created_at: DateTime<Utc>,
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
     pub updated_at: DateTime<Utc>,
 }
 
+/// Limits on where a coupon applies. Empty lists do not restrict anything.
+///
+/// A cart line is eligible when its product is not excluded and, if any
+/// products or categories are listed, it matches one of them. The minimum
+/// subtotal, in the coupon's currency, is compared with the eligible lines
+/// only.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct CouponRestrictions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minimum_subtotal: Option<Money>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub product_ids: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub category_ids: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excluded_product_ids: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub customer_groups: Vec<String>,
+    /// Tiers are matched by name, as in
+    /// [`Condition::MembershipTier`](crate::discount::Condition::MembershipTier).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub membership_tiers: Vec<MembershipTier>,
+}
+
+impl CouponRestrictions {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether checking the restrictions needs more than a cart total.
+    pub fn needs_cart(&self) -> bool {
+        !self.product_ids.is_empty()
+            || !self.category_ids.is_empty()
+            || !self.excluded_product_ids.is_empty()
+            || !self.customer_groups.is_empty()
+            || !self.membership_tiers.is_empty()
+    }
+
+    pub fn is_line_eligible(&self, line: &CartLine) -> bool {
+        if self.excluded_product_ids.contains(&line.product_id) {
+            return false;
+        }
+        (self.product_ids.is_empty() && self.category_ids.is_empty())
+            || self.product_ids.contains(&line.product_id)
+            || line.in_any_category(&self.category_ids)
+    }
+
+    pub fn eligible_subtotal(&self, cart: &Cart) -> Decimal {
+        cart.lines
+            .iter()
+            .filter(|line| self.is_line_eligible(line))
+            .map(CartLine::subtotal)
+            .sum()
+    }
+
+    /// Whether `subtotal` reaches the minimum, if there is one. The caller
+    /// checks that the minimum is in the subtotal's currency.
+    pub fn meets_minimum(&self, subtotal: Decimal) -> bool {
+        self.minimum_subtotal
+            .as_ref()
+            .is_none_or(|min| subtotal >= min.value())
+    }
+
+    /// The checks possible when all that is known is a cart `total`.
+    pub fn check_total(&self, total: Decimal) -> Result<(), CouponRejection> {
+        if self.needs_cart() {
+            Err(CouponRejection::CartRequired)
+        } else if !self.meets_minimum(total) {
+            Err(CouponRejection::BelowMinimumSubtotal)
+        } else {
+            Ok(())
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CouponDiscountType {
     Percentage,
     FixedAmount,
     FreeShipping,
 }
+
+/// Why a coupon cannot be used.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum CouponRejection {
+    Inactive,
+    NotYetActive,
+    Expired,
+    UsageLimitReached,
+    /// The customer has used up their own allowance, or is anonymous while
+    /// the coupon is limited per customer.
+    CustomerLimitReached,
+    /// The coupon was issued by another shop.
+    WrongShop,
+    /// The eligible lines add up to less than the minimum subtotal.
+    BelowMinimumSubtotal,
+    /// No line in the cart is eligible.
+    NoEligibleItems,
+    /// The customer is in none of the eligible customer groups.
+    CustomerGroupNotEligible,
+    /// The customer holds no valid membership in an eligible tier.
+    MembershipNotEligible,
+    /// Only a bare total was given, but the restrictions need a cart.
+    CartRequired,
+    /// The coupon is in `currency` but the cart is in `expected`.
+    CurrencyMismatch {
+        currency: Currency,
+        expected: Currency,
+    },
+}
+
+impl CouponRejection {
+    /// The RPC error reporting this rejection of the coupon `code`.
+    pub fn to_service_error(&self, code: &str) -> ServiceError {
+        let entity = Entity::Coupon;
+        let key = code.to_string();
+        match self {
+            CouponRejection::Inactive => ServiceError::Inactive { entity, key },
+            CouponRejection::NotYetActive => ServiceError::NotYetActive { entity, key },
+            CouponRejection::Expired => ServiceError::Expired { entity, key },
+            CouponRejection::UsageLimitReached => ServiceError::UsageLimitReached { entity, key },
+            CouponRejection::CustomerLimitReached => ServiceError::UsageLimitReached {
+                entity,
+                key: format!("{code} for this customer"),
+            },
+            CouponRejection::WrongShop => ServiceError::not_found(entity, key),
+            CouponRejection::BelowMinimumSubtotal => {
+                ServiceError::not_applicable(entity, key, "minimum subtotal not reached")
+            }
+            CouponRejection::NoEligibleItems => {
+                ServiceError::not_applicable(entity, key, "no eligible items in the cart")
+            }
+            CouponRejection::CustomerGroupNotEligible => {
+                ServiceError::not_applicable(entity, key, "customer group not eligible")
+            }
+            CouponRejection::MembershipNotEligible => {
+                ServiceError::not_applicable(entity, key, "membership tier not eligible")
+            }
+            CouponRejection::CartRequired => ServiceError::not_applicable(
+                entity,
+                key,
+                "its restrictions can only be checked against a cart",
+            ),
+            CouponRejection::CurrencyMismatch { currency, expected } => {
+                ServiceError::CurrencyMismatch {
+                    entity,
+                    key,
+                    currency: *currency,
+                    expected: *expected,
+                }
+            }
+        }
+    }
+}
+
+impl Coupon {
+    /// Checks the fields a stored coupon must have before it can be saved.
+    pub fn validate(&self) -> Result<(), ServiceError> {
+        let mut fields = Vec::new();
+        if self.shop_id.trim().is_empty() {
+            fields.push(FieldError::new("shop_id", "must not be empty"));
+        }
+        if self.code.trim().is_empty() {
+            fields.push(FieldError::new("code", "must not be empty"));
+        }
+        match decimal::from_bson(self.discount_value.amount) {
+            Ok(value) if value < Decimal::ZERO => {
+                fields.push(FieldError::new("discount_value", "must not be negative"))
+            }
+            Ok(value)
+                if matches!(self.discount_type, CouponDiscountType::Percentage)
+                    && value > Decimal::ONE_HUNDRED =>
+            {
+                fields.push(FieldError::new(
+                    "discount_value",
+                    "must be at most 100 percent",
+                ))
+            }
+            Ok(_) => {}
+            Err(e) => fields.push(FieldError::new("discount_value", e.to_string())),
+        }
+        if self.used_count < 0 {
+            fields.push(FieldError::new("used_count", "must not be negative"));
+        }
+        if self.max_uses.is_some_and(|max| max < 0) {
+            fields.push(FieldError::new("max_uses", "must not be negative"));
+        }
+        if let Some(min) = &self.restrictions.minimum_subtotal {
+            match decimal::from_bson(min.amount) {
+                Ok(amount) if amount >= Decimal::ZERO => {}
+                _ => fields.push(FieldError::new(
+                    "restrictions.minimum_subtotal",
+                    "must be a non-negative amount",
+                )),
+            }
+            if min.currency != self.discount_value.currency {
+                fields.push(FieldError::new(
+                    "restrictions.minimum_subtotal",
+                    "must be in the coupon's currency",
+                ));
+            }
+        }
+        if self.max_uses_per_customer.is_some_and(|max| max < 0) {
+            fields.push(FieldError::new(
+                "max_uses_per_customer",
+                "must not be negative",
+            ));
+        }
+        if let (Some(start), Some(end)) = (self.starts_at, self.expires_at)
+            && start > end
+        {
+            fields.push(FieldError::new(
+                "expires_at",
+                "must not be before starts_at",
+            ));
+        }
+        ServiceError::check_fields(fields)
+    }
+
+    /// Whether the coupon is switched on, inside its validity window and
+    /// below its usage limits at `now`.
+    pub fn availability_at(&self, now: DateTime<Utc>) -> Result<(), CouponRejection> {
+        if !self.is_active {
+            Err(CouponRejection::Inactive)
+        } else if self.starts_at.is_some_and(|start| now < start) {
+            Err(CouponRejection::NotYetActive)
+        } else if self.expires_at.is_some_and(|end| now > end) {
+            Err(CouponRejection::Expired)
+        } else if self.max_uses.is_some_and(|max| self.used_count >= max)
+            || (self.is_single_use && self.used_count > 0)
+        {
+            Err(CouponRejection::UsageLimitReached)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether one more use of the coupon would be allowed at `now`.
+    pub fn is_redeemable_at(&self, now: DateTime<Utc>) -> bool {
+        self.availability_at(now).is_ok()
+    }
+
+    /// Whether the customer and cart in `ctx` may use the coupon: it must be
+    /// available at `ctx.now`, belong to the cart's shop, be within the
+    /// customer's own allowance and pass its restrictions.
+    pub fn check(&self, ctx: &EvaluationContext) -> Result<(), CouponRejection> {
+        self.availability_at(ctx.now)?;
+        if self.shop_id != ctx.shop_id {
+            return Err(CouponRejection::WrongShop);
+        }
+        self.check_currency(ctx.cart.currency)?;
+        if !ctx.within_customer_limit(Entity::Coupon, &self.id, self.max_uses_per_customer) {
+            return Err(CouponRejection::CustomerLimitReached);
+        }
+        let restrictions = &self.restrictions;
+        if !restrictions.customer_groups.is_empty()
+            && !restrictions
+                .customer_groups
+                .iter()
+                .any(|g| ctx.customer_groups.contains(g))
+        {
+            return Err(CouponRejection::CustomerGroupNotEligible);
+        }
+        if !restrictions.membership_tiers.is_empty()
+            && !ctx.customer_membership.as_ref().is_some_and(|m| {
+                m.is_valid_at(ctx.now)
+                    && restrictions
+                        .membership_tiers
+                        .iter()
+                        .any(|tier| tier.name == m.tier.name)
+            })
+        {
+            return Err(CouponRejection::MembershipNotEligible);
+        }
+        if !ctx
+            .cart
+            .lines
+            .iter()
+            .any(|line| line.quantity > 0 && restrictions.is_line_eligible(line))
+        {
+            return Err(CouponRejection::NoEligibleItems);
+        }
+        if !restrictions.meets_minimum(restrictions.eligible_subtotal(&ctx.cart)) {
+            return Err(CouponRejection::BelowMinimumSubtotal);
+        }
+        Ok(())
+    }
+
+    /// Fails when the coupon's amounts are not all in `currency`.
+    pub fn check_currency(&self, currency: Currency) -> Result<(), CouponRejection> {
+        let minimum = self.restrictions.minimum_subtotal.as_ref();
+        match std::iter::once(self.discount_value.currency)
+            .chain(minimum.map(|min| min.currency))
+            .find(|c| *c != currency)
+        {
+            Some(found) => Err(CouponRejection::CurrencyMismatch {
+                currency: found,
+                expected: currency,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// What the coupon takes off a merchandise `total` in its currency,
+    /// never more than the total itself. Free shipping coupons take nothing
+    /// off it.
+    pub fn discount_on(&self, total: Decimal) -> Decimal {
+        let total = total.max(Decimal::ZERO);
+        let currency = self.discount_value.currency;
+        let value = self.discount_value.value().max(Decimal::ZERO);
+        let amount = match self.discount_type {
+            CouponDiscountType::Percentage => {
+                currency.round(decimal::percent_of(total, value.min(Decimal::ONE_HUNDRED)))
+            }
+            CouponDiscountType::FixedAmount => currency.round(value),
+            CouponDiscountType::FreeShipping => Decimal::ZERO,
+        };
+        amount.min(total)
+    }
+
+    /// The total left after taking this coupon's discount off `total`.
+    pub fn apply_to_total(&self, total: Decimal) -> Decimal {
+        let total = total.max(Decimal::ZERO);
+        total - self.discount_on(total)
+    }
+}
 #[tarpc::service]
 pub trait CouponService {
-    async fn create_coupon(coupon: Coupon) -> Result<Coupon, String>;
-    async fn get_coupon(id: String) -> Result<Coupon, String>;
-    async fn update_coupon(coupon: Coupon) -> Result<Coupon, String>;
-    async fn delete_coupon(id: String) -> Result<(), String>;
-    async fn list_coupons(shop_id: String) -> Result<Vec<Coupon>, String>;
+    async fn create_coupon(coupon: Coupon) -> Result<Coupon, ServiceError>;
+    async fn get_coupon(id: String) -> Result<Coupon, ServiceError>;
+    async fn update_coupon(coupon: Coupon) -> Result<Coupon, ServiceError>;
+    async fn delete_coupon(id: String) -> Result<(), ServiceError>;
+    async fn list_coupons(shop_id: String) -> Result<Vec<Coupon>, ServiceError>;
+    /// Redeems the shop's coupon and returns the discounted total.
+    /// `customer_id` is required when the coupon is limited per customer, and
+    /// the total must be in the coupon's currency.
     async fn apply_coupon(
         coupon_code: String,
-        cart_total: Decimal128,
-    ) -> Result<Decimal128, String>;
-    async fn validate_coupon(coupon_code: String) -> Result<bool, String>;
-    async fn get_coupon_by_code(coupon_code: String) -> Result<Coupon, String>;
+        shop_id: String,
+        customer_id: Option<String>,
+        cart_total: Money,
+    ) -> Result<Money, ServiceError>;
+    /// Gives back a use taken by `apply_coupon` for an order that was
+    /// cancelled.
+    async fn release_coupon(
+        coupon_code: String,
+        shop_id: String,
+        customer_id: Option<String>,
+    ) -> Result<Coupon, ServiceError>;
+    async fn validate_coupon(coupon_code: String, shop_id: String) -> Result<bool, ServiceError>;
+    async fn get_coupon_by_code(coupon_code: String) -> Result<Coupon, ServiceError>;
+    /// Creates `count` single-use coupons for `campaign_id` with codes drawn
+    /// from `template`. Every other field is copied from `prototype`, whose
+    /// id and code are ignored.
+    async fn generate_coupon_batch(
+        campaign_id: String,
+        template: CodeTemplate,
+        count: u32,
+        prototype: Coupon,
+    ) -> Result<Vec<Coupon>, ServiceError>;
+    async fn list_campaign_coupons(
+        shop_id: String,
+        campaign_id: String,
+    ) -> Result<Vec<Coupon>, ServiceError>;
     async fn get_coupon_by_code_and_shop(
         coupon_code: String,
         shop_id: String,
-    ) -> Result<Coupon, String>;
+    ) -> Result<Coupon, ServiceError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cart::Cart,
+        fixtures::{self, cart, ctx, dec, line, usd},
+        membership::MembershipTarget,
+    };
+
+    fn coupon() -> Coupon {
+        fixtures::coupon("coupon-1", "shop-1", "SAVE10", "10")
+    }
+
+    fn round_trip(coupon: &Coupon) -> Coupon {
+        bson::from_document(bson::to_document(coupon).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_undated_coupon_round_trips_through_bson() {
+        let stored = round_trip(&coupon());
+        assert_eq!(stored.starts_at, None);
+        assert_eq!(stored.expires_at, None);
+    }
+
+    #[test]
+    fn test_dated_coupon_round_trips_through_bson() {
+        let mut coupon = coupon();
+        coupon.starts_at = Some(coupon.created_at);
+        coupon.expires_at = Some(coupon.created_at + chrono::Duration::days(30));
+        let stored = round_trip(&coupon);
+        assert_eq!(stored.starts_at, coupon.starts_at);
+        assert_eq!(stored.expires_at, coupon.expires_at);
+    }
+
+    #[test]
+    fn test_availability_follows_dates_and_uses() {
+        let now = fixtures::now();
+        let mut coupon = coupon();
+        coupon.starts_at = Some(now + chrono::Duration::hours(1));
+        assert_eq!(
+            coupon.availability_at(now),
+            Err(CouponRejection::NotYetActive)
+        );
+        coupon.starts_at = None;
+        coupon.expires_at = Some(now - chrono::Duration::hours(1));
+        assert_eq!(coupon.availability_at(now), Err(CouponRejection::Expired));
+        coupon.expires_at = None;
+        coupon.is_single_use = true;
+        coupon.used_count = 1;
+        assert_eq!(
+            coupon.availability_at(now),
+            Err(CouponRejection::UsageLimitReached)
+        );
+        coupon.is_active = false;
+        assert_eq!(coupon.availability_at(now), Err(CouponRejection::Inactive));
+    }
+
+    #[test]
+    fn test_minimum_subtotal_in_another_currency_is_a_mismatch() {
+        let mut coupon = coupon();
+        coupon.restrictions.minimum_subtotal =
+            Some(Money::new(Decimal::ONE_HUNDRED, Currency::JPY));
+        assert_eq!(
+            coupon.check_currency(Currency::USD),
+            Err(CouponRejection::CurrencyMismatch {
+                currency: Currency::JPY,
+                expected: Currency::USD,
+            })
+        );
+        assert!(coupon.validate().is_err());
+    }
+
+    #[test]
+    fn test_legacy_bare_minimum_subtotal_reads_as_us_dollars() {
+        let restrictions: CouponRestrictions = bson::from_document(bson::doc! {
+            "minimum_subtotal": decimal::to_bson(Decimal::ONE_HUNDRED),
+        })
+        .unwrap();
+        assert_eq!(
+            restrictions.minimum_subtotal,
+            Some(Money::new(Decimal::ONE_HUNDRED, Currency::USD))
+        );
+    }
+
+    fn shirts_and_socks() -> Cart {
+        cart(vec![
+            line("shirt", "shirt", "30", 1).with_categories(vec!["clothing".to_string()]),
+            line("socks", "socks", "5", 2),
+        ])
+    }
+
+    #[test]
+    fn test_restricted_lines_are_the_only_eligible_ones() {
+        let restrictions = CouponRestrictions {
+            category_ids: vec!["clothing".to_string()],
+            excluded_product_ids: vec!["shirt".to_string()],
+            ..Default::default()
+        };
+        let cart = shirts_and_socks();
+        assert!(!restrictions.is_line_eligible(&cart.lines[0]));
+        assert!(!restrictions.is_line_eligible(&cart.lines[1]));
+
+        let restrictions = CouponRestrictions {
+            product_ids: vec!["socks".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(restrictions.eligible_subtotal(&cart), dec("10"));
+    }
+
+    #[test]
+    fn test_minimum_subtotal_counts_eligible_lines_only() {
+        let mut coupon = coupon();
+        coupon.restrictions = CouponRestrictions {
+            minimum_subtotal: Some(usd("20")),
+            product_ids: vec!["socks".to_string()],
+            ..Default::default()
+        };
+        let ctx = ctx(shirts_and_socks());
+        assert_eq!(
+            coupon.check(&ctx),
+            Err(CouponRejection::BelowMinimumSubtotal)
+        );
+        coupon.restrictions.product_ids.push("shirt".to_string());
+        assert_eq!(coupon.check(&ctx), Ok(()));
+    }
+
+    #[test]
+    fn test_check_explains_each_rejection() {
+        let ctx = ctx(shirts_and_socks());
+        let check = |change: &dyn Fn(&mut Coupon)| {
+            let mut coupon = coupon();
+            change(&mut coupon);
+            coupon.check(&ctx)
+        };
+        assert_eq!(check(&|_| {}), Ok(()));
+        assert_eq!(
+            check(&|c| c.restrictions.customer_groups = vec!["vip".to_string()]),
+            Err(CouponRejection::CustomerGroupNotEligible)
+        );
+        assert_eq!(
+            check(&|c| {
+                c.restrictions.membership_tiers =
+                    vec![MembershipTier::new("Gold", MembershipTarget::Customer)]
+            }),
+            Err(CouponRejection::MembershipNotEligible)
+        );
+        assert_eq!(
+            check(&|c| c.restrictions.product_ids = vec!["hat".to_string()]),
+            Err(CouponRejection::NoEligibleItems)
+        );
+        assert_eq!(
+            check(&|c| c.shop_id = "shop-2".to_string()),
+            Err(CouponRejection::WrongShop)
+        );
+        assert_eq!(
+            check(&|c| c.max_uses_per_customer = Some(1)),
+            Err(CouponRejection::CustomerLimitReached)
+        );
+    }
+
+    #[test]
+    fn test_discount_never_exceeds_the_total() {
+        let mut coupon = coupon();
+        coupon.discount_type = CouponDiscountType::FixedAmount;
+        coupon.discount_value = usd("25");
+        assert_eq!(coupon.discount_on(dec("20")), dec("20"));
+        assert_eq!(coupon.apply_to_total(dec("30")), dec("5"));
+
+        coupon.discount_type = CouponDiscountType::Percentage;
+        coupon.discount_value = usd("12.5");
+        assert_eq!(coupon.discount_on(dec("0.99")), dec("0.12"));
+
+        coupon.discount_type = CouponDiscountType::FreeShipping;
+        assert_eq!(coupon.discount_on(dec("30")), dec("0"));
+    }
+
+    #[test]
+    fn test_restrictions_needing_a_cart_refuse_a_bare_total() {
+        let restrictions = CouponRestrictions {
+            excluded_product_ids: vec!["socks".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            restrictions.check_total(dec("100")),
+            Err(CouponRejection::CartRequired)
+        );
+        let restrictions = CouponRestrictions {
+            minimum_subtotal: Some(usd("50")),
+            ..Default::default()
+        };
+        assert_eq!(
+            restrictions.check_total(dec("40")),
+            Err(CouponRejection::BelowMinimumSubtotal)
+        );
+        assert_eq!(restrictions.check_total(dec("50")), Ok(()));
+    }
 }
@@ -0,0 +1,282 @@
+use rand::{Rng, seq::SliceRandom};
+use serde::{Deserialize, Serialize};
+
+use crate::error::FieldError;
+
+/// Upper-case letters and digits without the look-alikes 0/O, 1/I/L.
+pub const UNAMBIGUOUS_ALPHABET: &str = "ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+
+/// Most coupons one batch may create.
+pub const MAX_BATCH_SIZE: u32 = 100_000;
+
+/// How sparse generated codes must be: a template has to allow at least this
+/// many codes per coupon requested, so guessing a valid code stays unlikely
+/// and random collisions stay rare.
+const MIN_SPARSENESS: f64 = 100.0;
+
+/// Most random characters a template may ask for.
+pub const MAX_CODE_LENGTH: u32 = 32;
+
+/// Canonical form of a code as typed by a shopper: surrounding whitespace
+/// trimmed, upper-cased, and dashes and inner spaces removed, so
+/// `" xmas-ab3d "` and `"XMASAB3D"` are the same code.
+pub fn normalize(code: &str) -> String {
+    code.chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .flat_map(char::to_uppercase)
+        .collect()
+}
+
+/// Shape of generated coupon codes: `prefix`, then `length` random characters
+/// drawn from `alphabet`, then, with `checksum`, one check character.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CodeTemplate {
+    #[serde(default)]
+    pub prefix: String,
+    pub length: u32,
+    #[serde(default = "default_alphabet")]
+    pub alphabet: String,
+    /// Appends a check character computed over the random part, so mistyped
+    /// codes can be rejected without a lookup; see
+    /// [`CodeTemplate::check_character`].
+    #[serde(default)]
+    pub checksum: bool,
+}
+
+fn default_alphabet() -> String {
+    UNAMBIGUOUS_ALPHABET.to_string()
+}
+
+impl CodeTemplate {
+    pub fn new(prefix: impl Into<String>, length: u32) -> Self {
+        Self {
+            prefix: prefix.into(),
+            length,
+            alphabet: default_alphabet(),
+            checksum: false,
+        }
+    }
+
+    pub fn with_alphabet(mut self, alphabet: impl Into<String>) -> Self {
+        self.alphabet = alphabet.into();
+        self
+    }
+
+    pub fn with_checksum(mut self) -> Self {
+        self.checksum = true;
+        self
+    }
+
+    /// Problems with the template for a batch of `count` codes, reported
+    /// under `template.*` fields.
+    pub fn field_errors(&self, count: u32) -> Vec<FieldError> {
+        let mut fields = Vec::new();
+        let field = |name: &str| format!("template.{name}");
+        if !self
+            .prefix
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+        {
+            fields.push(FieldError::new(
+                field("prefix"),
+                "must only hold upper-case letters and digits",
+            ));
+        }
+        let alphabet = self.symbols();
+        let mut unique = alphabet.clone();
+        unique.sort_unstable();
+        unique.dedup();
+        if alphabet.len() < 2
+            || unique.len() != alphabet.len()
+            || !alphabet
+                .iter()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+        {
+            fields.push(FieldError::new(
+                field("alphabet"),
+                "must hold at least two distinct upper-case letters or digits",
+            ));
+        } else if self.length > MAX_CODE_LENGTH {
+            fields.push(FieldError::new(
+                field("length"),
+                format!("must be at most {MAX_CODE_LENGTH}"),
+            ));
+        } else if (alphabet.len() as f64).powi(self.length as i32)
+            < f64::from(count) * MIN_SPARSENESS
+        {
+            fields.push(FieldError::new(
+                field("length"),
+                "allows too few codes for the batch size",
+            ));
+        }
+        fields
+    }
+
+    /// One random code. Assumes the template passed [`Self::field_errors`].
+    pub fn generate<R: Rng + ?Sized>(&self, rng: &mut R) -> String {
+        let alphabet = self.symbols();
+        let body: String = (0..self.length)
+            .filter_map(|_| alphabet.choose(rng))
+            .collect();
+        let mut code = format!("{}{body}", self.prefix);
+        if self.checksum
+            && let Some(check) = self.check_character(&body)
+        {
+            code.push(check);
+        }
+        code
+    }
+
+    /// Whether `code` has this template's shape: its prefix followed by as
+    /// many characters as a generated code carries. Normalize it first.
+    pub fn claims(&self, code: &str) -> bool {
+        let extra = usize::from(self.checksum);
+        code.strip_prefix(self.prefix.as_str())
+            .is_some_and(|rest| rest.chars().count() == self.length as usize + extra)
+    }
+
+    /// Whether a code this template [claims](Self::claims) could have been
+    /// generated by it: every character from the alphabet and, with
+    /// `checksum`, a matching check character.
+    pub fn accepts(&self, code: &str) -> bool {
+        let Some(rest) = code.strip_prefix(self.prefix.as_str()) else {
+            return false;
+        };
+        if !rest.chars().all(|c| self.alphabet.contains(c)) {
+            return false;
+        }
+        if !self.checksum {
+            return true;
+        }
+        let mut body = rest.to_string();
+        let check = body.pop();
+        check.is_some() && self.check_character(&body) == check
+    }
+
+    /// The check character for `body`, or `None` when `body` holds a
+    /// character outside the alphabet.
+    ///
+    /// Every other character from the end is doubled. With an even-sized
+    /// alphabet this is Luhn mod N, which folds a doubled value back into
+    /// range by adding its base-N digits. Folding only keeps characters apart
+    /// when N is even, so with an odd-sized alphabet, such as the default
+    /// one, doubled values are taken mod N instead. Either way a single
+    /// mistyped character always changes the check character.
+    pub fn check_character(&self, body: &str) -> Option<char> {
+        let alphabet = self.symbols();
+        let n = alphabet.len() as u32;
+        let mut factor = 2;
+        let mut sum = 0;
+        for c in body.chars().rev() {
+            let addend = factor * alphabet.iter().position(|a| *a == c)? as u32;
+            sum += if n.is_multiple_of(2) {
+                addend / n + addend % n
+            } else {
+                addend % n
+            };
+            factor = if factor == 2 { 1 } else { 2 };
+        }
+        alphabet.get(((n - sum % n) % n) as usize).copied()
+    }
+
+    fn symbols(&self) -> Vec<char> {
+        self.alphabet.chars().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    #[test]
+    fn test_generated_codes_follow_the_template() {
+        let template = CodeTemplate::new("XMAS", 8).with_checksum();
+        let mut rng = StdRng::seed_from_u64(7);
+        let codes: HashSet<String> = (0..50).map(|_| template.generate(&mut rng)).collect();
+        assert_eq!(codes.len(), 50);
+        for code in &codes {
+            assert_eq!(code.len(), 4 + 8 + 1, "{code}");
+            let (body, check) = code["XMAS".len()..].split_at(8);
+            assert!(code.starts_with("XMAS"), "{code}");
+            assert!(
+                body.chars().all(|c| UNAMBIGUOUS_ALPHABET.contains(c)),
+                "{code}"
+            );
+            assert_eq!(template.check_character(body), check.chars().next());
+        }
+    }
+
+    #[test]
+    fn test_check_character_is_luhn_over_decimal_digits() {
+        let digits = CodeTemplate::new("", 10).with_alphabet("0123456789");
+        assert_eq!(digits.check_character("7992739871"), Some('3'));
+        assert_eq!(digits.check_character("79927A9871"), None);
+    }
+
+    #[test]
+    fn test_checksum_catches_every_single_character_slip() {
+        let template = CodeTemplate::new("XMAS", 8).with_checksum();
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..50 {
+            let code = template.generate(&mut rng);
+            assert!(template.claims(&code) && template.accepts(&code), "{code}");
+            let chars: Vec<char> = code.chars().collect();
+            for i in template.prefix.len()..chars.len() {
+                for typo in UNAMBIGUOUS_ALPHABET.chars().filter(|c| *c != chars[i]) {
+                    let mut mistyped = chars.clone();
+                    mistyped[i] = typo;
+                    let mistyped: String = mistyped.into_iter().collect();
+                    assert!(!template.accepts(&mistyped), "{mistyped}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_normalize_ignores_case_dashes_and_spaces() {
+        assert_eq!(normalize(" xmas-ab3d "), "XMASAB3D");
+        assert_eq!(normalize("Xmas AB 3d"), "XMASAB3D");
+    }
+
+    #[test]
+    fn test_claims_only_codes_of_the_template_shape() {
+        let template = CodeTemplate::new("XMAS", 4).with_checksum();
+        assert!(template.claims("XMASABCDE"));
+        assert!(!template.claims("XMASABCD"));
+        assert!(!template.claims("EASTABCDE"));
+        assert!(!CodeTemplate::new("XMAS", 4).accepts("XMASAB0D"));
+    }
+
+    #[test]
+    fn test_field_errors_check_prefix_alphabet_and_sparseness() {
+        let fields = |template: CodeTemplate, count| -> Vec<String> {
+            template
+                .field_errors(count)
+                .into_iter()
+                .map(|f| f.field)
+                .collect()
+        };
+        assert!(fields(CodeTemplate::new("XMAS", 8), 1_000).is_empty());
+        assert_eq!(
+            fields(CodeTemplate::new("xmas", 8).with_alphabet("AA"), 1),
+            vec!["template.prefix", "template.alphabet"]
+        );
+        assert_eq!(
+            fields(CodeTemplate::new("XMAS", 2), 100),
+            vec!["template.length"]
+        );
+        assert_eq!(
+            fields(CodeTemplate::new("XMAS", u32::MAX), 1),
+            vec!["template.length"]
+        );
+        assert!(
+            !CodeTemplate::new("XMAS", u32::MAX)
+                .with_checksum()
+                .claims("XMASAB3D")
+        );
+    }
+}
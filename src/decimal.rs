@@ -0,0 +1,172 @@
+use std::str::FromStr;
+
+use bson::Decimal128;
+use thiserror::Error;
+
+pub use rust_decimal::Decimal;
+
+use crate::discount::Operator;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum DecimalError {
+    #[error("{0} is not a finite number")]
+    NotFinite(String),
+    #[error("{0} cannot be represented exactly")]
+    OutOfRange(String),
+    #[error("{0} is not a valid decimal")]
+    Invalid(String),
+}
+
+/// Converts a BSON `Decimal128` to an exact `Decimal`.
+///
+/// NaN and the infinities are rejected, as is anything that would need
+/// rounding to fit in 28 significant digits. Negative zero becomes zero.
+pub fn from_bson(value: Decimal128) -> Result<Decimal, DecimalError> {
+    let text = value.to_string();
+    let lowered = text.to_ascii_lowercase();
+    if lowered.contains("nan") || lowered.contains("inf") {
+        return Err(DecimalError::NotFinite(text));
+    }
+
+    let (mantissa, exponent) = match lowered.split_once('e') {
+        Some((mantissa, exponent)) => {
+            let exponent = exponent
+                .parse::<i32>()
+                .map_err(|_| DecimalError::Invalid(text.clone()))?;
+            (mantissa, exponent)
+        }
+        None => (lowered.as_str(), 0),
+    };
+    let mantissa =
+        Decimal::from_str_exact(mantissa).map_err(|_| DecimalError::OutOfRange(text.clone()))?;
+
+    let value = if exponent >= 0 {
+        let factor = 10i128
+            .checked_pow(exponent as u32)
+            .and_then(|f| Decimal::try_from_i128_with_scale(f, 0).ok())
+            .ok_or_else(|| DecimalError::OutOfRange(text.clone()))?;
+        mantissa
+            .checked_mul(factor)
+            .ok_or_else(|| DecimalError::OutOfRange(text.clone()))?
+    } else {
+        let scale = mantissa.scale() + exponent.unsigned_abs();
+        Decimal::try_from_i128_with_scale(mantissa.mantissa(), scale)
+            .map_err(|_| DecimalError::OutOfRange(text.clone()))?
+    };
+
+    Ok(if value.is_zero() {
+        Decimal::ZERO
+    } else {
+        value
+    })
+}
+
+/// Like [`from_bson`], but treats values that cannot be used for money
+/// (NaN, infinities, out of range) as zero.
+pub fn from_bson_or_zero(value: Decimal128) -> Decimal {
+    from_bson(value).unwrap_or(Decimal::ZERO)
+}
+
+pub fn to_bson(value: Decimal) -> Decimal128 {
+    Decimal128::from_str(&value.to_string())
+        .expect("a Decimal always formats as a valid Decimal128")
+}
+
+/// `percent` percent of `amount`, unrounded.
+pub fn percent_of(amount: Decimal, percent: Decimal) -> Decimal {
+    amount * percent / Decimal::ONE_HUNDRED
+}
+
+pub fn compare(a: Decimal, b: Decimal, op: &Operator) -> bool {
+    match op {
+        Operator::Equal => a == b,
+        Operator::NotEqual => a != b,
+        Operator::GreaterThan => a > b,
+        Operator::LessThan => a < b,
+        Operator::GreaterThanOrEqual => a >= b,
+        Operator::LessThanOrEqual => a <= b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{d128, dec};
+
+    #[test]
+    fn test_point_one_plus_point_two() {
+        let sum = from_bson(d128("0.1")).unwrap() + from_bson(d128("0.2")).unwrap();
+        assert_eq!(sum, dec("0.3"));
+        assert!(compare(
+            sum,
+            from_bson(d128("0.3")).unwrap(),
+            &Operator::Equal
+        ));
+    }
+
+    #[test]
+    fn test_non_finite_values_are_rejected() {
+        for s in ["NaN", "Infinity", "-Infinity"] {
+            assert!(matches!(
+                from_bson(d128(s)),
+                Err(DecimalError::NotFinite(_))
+            ));
+        }
+        assert_eq!(from_bson_or_zero(d128("NaN")), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_compare_decimal_never_matches_non_finite() {
+        use crate::discount::compare_decimal;
+
+        let hundred = d128("100");
+        for op in [Operator::Equal, Operator::NotEqual, Operator::GreaterThan] {
+            assert!(!compare_decimal(d128("NaN"), hundred, &op));
+            assert!(!compare_decimal(d128("Infinity"), hundred, &op));
+        }
+        assert!(compare_decimal(d128("-0"), d128("0.00"), &Operator::Equal));
+        assert!(compare_decimal(d128("1E+2"), hundred, &Operator::Equal));
+    }
+
+    #[test]
+    fn test_negative_zero_equals_zero() {
+        let negative_zero = from_bson(d128("-0")).unwrap();
+        assert_eq!(negative_zero, Decimal::ZERO);
+        assert!(negative_zero.is_sign_positive());
+        assert_eq!(to_bson(negative_zero).to_string(), "0");
+    }
+
+    #[test]
+    fn test_exponents_are_exact() {
+        assert_eq!(from_bson(d128("1.05E+3")).unwrap(), dec("1050"));
+        assert_eq!(from_bson(d128("25E-2")).unwrap(), dec("0.25"));
+        assert_eq!(
+            from_bson(d128("1E-30")),
+            Err(DecimalError::OutOfRange("1E-30".into()))
+        );
+        assert!(from_bson(d128("1E+40")).is_err());
+    }
+
+    #[test]
+    fn test_precision_beyond_28_digits_is_rejected() {
+        let long = "1.234567890123456789012345678901";
+        assert!(matches!(
+            from_bson(d128(long)),
+            Err(DecimalError::OutOfRange(_))
+        ));
+    }
+
+    #[test]
+    fn test_round_trip_keeps_scale() {
+        assert_eq!(
+            to_bson(from_bson(d128("10.50")).unwrap()).to_string(),
+            "10.50"
+        );
+    }
+
+    #[test]
+    fn test_percent_of_is_unrounded() {
+        assert_eq!(percent_of(dec("19.99"), dec("15")), dec("2.9985"));
+    }
+}
@@ -1,12 +1,18 @@
-use std::collections::HashMap;
-
 use crate::{
+    cart::{Cart, CartLine},
     coupon::Coupon,
+    coupon_code,
     datetime::datetime_serialization,
+    decimal::{self, Decimal},
+    error::{Entity, FieldError, ServiceError},
     membership::{Membership, MembershipTier},
+    money::{Currency, Money},
+    redemption::Redemption,
+    schedule::{ActivationWindow, Recurrence},
 };
 use bson::Decimal128;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Datelike, Duration, Timelike, Utc};
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -26,18 +32,36 @@ pub struct DiscountRule {
     pub id: String,
     pub shop_id: String,
     pub name: String,
+    /// Code customers enter to trigger the rule; unique within a shop.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
     pub conditions: Vec<Condition>,
     pub actions: Vec<DiscountAction>,
     pub priority: i32,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     #[serde(with = "datetime_serialization")]
     pub start_date: Option<DateTime<Utc>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     #[serde(with = "datetime_serialization")]
     pub end_date: Option<DateTime<Utc>>,
     pub is_active: bool,
     pub usage_count: i32,
     pub max_usage: Option<i32>,
+    /// How many times one customer may use the rule. A rule with a
+    /// per-customer limit never applies to an anonymous customer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_usage_per_customer: Option<i32>,
+    /// When applied, stops any lower priority rule from applying.
+    #[serde(default)]
+    pub is_exclusive: bool,
+    /// Rules sharing a stacking group never combine; only the highest
+    /// priority one applies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stacking_group: Option<String>,
+    /// Repeating schedule the rule is limited to inside its date window,
+    /// read in the shop's time zone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recurrence: Option<Recurrence>,
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
     pub created_at: DateTime<Utc>,
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
@@ -48,7 +72,7 @@ pub struct DiscountRule {
 pub enum Condition {
     CartTotal {
         operator: Operator,
-        value: Decimal128,
+        value: Money,
     },
     ProductCategory {
         category_ids: Vec<String>,
@@ -56,14 +80,32 @@ pub enum Condition {
     CustomerGroup {
         group_ids: Vec<String>,
     },
+    /// At least `min_orders` orders in the last `timeframe_days` days, or
+    /// ever when `timeframe_days` is not positive.
     PurchaseHistory {
         min_orders: i32,
         timeframe_days: i32,
     },
+    /// At least `min_amount` spent in the last `timeframe_days` days, or ever
+    /// when `timeframe_days` is not positive. Only orders in `min_amount`'s
+    /// currency count.
+    SpendHistory {
+        min_amount: Money,
+        timeframe_days: i32,
+    },
+    /// Local time in the shop's time zone between `start_hour:start_minute`
+    /// and `end_hour:end_minute`, both inclusive. A start later than the end
+    /// is an overnight window, e.g. 22:00 to 01:59.
     TimeOfDay {
         start_hour: i32,
+        #[serde(default)]
+        start_minute: i32,
         end_hour: i32,
+        #[serde(default = "last_minute")]
+        end_minute: i32,
     },
+    /// Local day in the shop's time zone, 0 for Sunday through 6 for
+    /// Saturday.
     DayOfWeek {
         days: Vec<u8>,
     },
@@ -77,12 +119,32 @@ pub enum Condition {
         code: String,
     },
     MinimumSpend {
-        amount: Decimal128,
+        amount: Money,
     },
     MembershipTier {
         tiers: Vec<MembershipTier>,
     },
     MembershipActive,
+    /// Passes when the cart holds at least one complete bundle.
+    Bundle {
+        components: Vec<BundleComponent>,
+    },
+    /// Passes when every nested condition passes; an empty list passes.
+    All {
+        conditions: Vec<Condition>,
+    },
+    /// Passes when at least one nested condition passes; an empty list fails.
+    Any {
+        conditions: Vec<Condition>,
+    },
+    Not {
+        condition: Box<Condition>,
+    },
+}
+
+/// Rules stored before minutes existed meant "until the end of `end_hour`".
+fn last_minute() -> i32 {
+    59
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -91,108 +153,680 @@ pub enum DiscountAction {
         percent: Decimal128,
     },
     FixedAmountOff {
-        amount: Decimal128,
+        amount: Money,
     },
     FreeShipping,
+    /// Each time `buy_quantity` units from `buy` are bought, `get_quantity`
+    /// units from `get` are discounted, cheapest first. The two selectors may
+    /// overlap, as in "buy any 2 shirts, get the cheapest shirt free".
     BuyXGetY {
-        buy_product_id: String,
+        /// Older rules stored a single product id as `buy_product_id`.
+        #[serde(alias = "buy_product_id")]
+        buy: ItemSelector,
         buy_quantity: i32,
-        get_product_id: String,
+        #[serde(alias = "get_product_id")]
+        get: ItemSelector,
         get_quantity: i32,
+        /// Percentage taken off each discounted unit; 100 makes them free.
+        #[serde(default = "full_discount")]
+        get_percent_off: Decimal128,
+        /// How many times the offer applies to one order, 1 for once per
+        /// order. Unset, it repeats as often as the cart allows.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_applications: Option<i32>,
+    },
+    /// Volume pricing on the units `items` selects, counted together across
+    /// lines: "10% off 10-49 units, 15% off 50 or more".
+    QuantityTiers {
+        #[serde(default)]
+        items: ItemSelector,
+        value_type: TierValueType,
+        tiers: Vec<QuantityTier>,
+        mode: TierMode,
+        /// Currency of fixed amount tier values.
+        #[serde(default)]
+        currency: Currency,
+    },
+    /// Savings by what is spent on the lines `items` selects: "spend 100
+    /// save 10, spend 200 save 30".
+    SpendTiers {
+        #[serde(default)]
+        items: ItemSelector,
+        value_type: TierValueType,
+        tiers: Vec<SpendTier>,
+        mode: TierMode,
+        /// Currency of the thresholds and of fixed amount tier values.
+        #[serde(default)]
+        currency: Currency,
     },
+    /// Sells each complete bundle of `components` for `price`, taking the
+    /// difference from the regular price of the units it uses.
+    BundlePrice {
+        components: Vec<BundleComponent>,
+        price: Money,
+        /// How many bundles one order may get at the bundle price. Unset, every
+        /// complete bundle does.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_applications: Option<i32>,
+    },
+}
+
+/// `quantity` units of a product that a bundle needs.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BundleComponent {
+    pub product_id: String,
+    pub quantity: i32,
+}
+
+impl BundleComponent {
+    pub fn new(product_id: impl Into<String>, quantity: i32) -> Self {
+        Self {
+            product_id: product_id.into(),
+            quantity,
+        }
+    }
+}
+
+/// How many complete bundles of `components` fit in `cart`; none when the
+/// list is empty or a quantity is not positive.
+pub fn bundle_count(cart: &Cart, components: &[BundleComponent]) -> i32 {
+    if components.iter().any(|c| c.quantity <= 0) {
+        return 0;
+    }
+    let quantities = cart.product_quantities();
+    components
+        .iter()
+        .map(|c| quantities.get(&c.product_id).copied().unwrap_or(0).max(0) / c.quantity)
+        .min()
+        .unwrap_or(0)
+}
+
+/// What a tier's `value` means. For quantity tiers a fixed amount comes off
+/// each unit; for spend tiers it comes off once. Fixed amounts are in the
+/// tiered action's currency.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TierValueType {
+    Percentage,
+    FixedAmount,
+}
+
+/// How a tiered action treats the quantity or spend it covers.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TierMode {
+    /// Everything gets the value of the highest tier reached.
+    WholeAtReachedTier,
+    /// Each tier's value covers only its own band, from its threshold up to
+    /// the next tier's. A fixed amount spend tier adds its amount once the
+    /// band is entered.
+    Graduated,
+}
+
+/// Applies from the `min_quantity`th unit on. Tiers are listed in ascending
+/// order of `min_quantity`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct QuantityTier {
+    pub min_quantity: i32,
+    pub value: Decimal128,
+}
+
+/// Applies once `min_spend` is reached. Tiers are listed in ascending order
+/// of `min_spend`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SpendTier {
+    pub min_spend: Money,
+    pub value: Decimal128,
+}
+
+fn full_discount() -> Decimal128 {
+    decimal::to_bson(Decimal::ONE_HUNDRED)
+}
+
+/// Picks cart lines by product or category. A line matches when its product
+/// is listed or it is in a listed category; an empty selector matches every
+/// line. A bare product id string is read as a selector for that product.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(from = "ItemSelectorRepr")]
+pub struct ItemSelector {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub product_ids: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub category_ids: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ItemSelectorRepr {
+    Product(String),
+    Selector {
+        #[serde(default)]
+        product_ids: Vec<String>,
+        #[serde(default)]
+        category_ids: Vec<String>,
+    },
+}
+
+impl From<ItemSelectorRepr> for ItemSelector {
+    fn from(repr: ItemSelectorRepr) -> Self {
+        match repr {
+            ItemSelectorRepr::Product(product_id) => Self::products(vec![product_id]),
+            ItemSelectorRepr::Selector {
+                product_ids,
+                category_ids,
+            } => Self {
+                product_ids,
+                category_ids,
+            },
+        }
+    }
+}
+
+impl ItemSelector {
+    pub fn products(product_ids: Vec<String>) -> Self {
+        Self {
+            product_ids,
+            category_ids: Vec::new(),
+        }
+    }
+
+    pub fn categories(category_ids: Vec<String>) -> Self {
+        Self {
+            product_ids: Vec::new(),
+            category_ids,
+        }
+    }
+
+    pub fn matches(&self, line: &CartLine) -> bool {
+        (self.product_ids.is_empty() && self.category_ids.is_empty())
+            || self.product_ids.contains(&line.product_id)
+            || line.in_any_category(&self.category_ids)
+    }
+}
+
+/// A past order of the customer, as far as history conditions care.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderRecord {
+    pub order_id: String,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub placed_at: DateTime<Utc>,
+    pub amount: Money,
 }
 
 #[derive(Debug, Clone)]
 pub struct EvaluationContext {
     pub shop_id: String,
-    pub cart_total: Decimal128,
-    pub product_quantities: HashMap<String, i32>,
-    pub product_categories: HashMap<String, Vec<String>>,
+    pub cart: Cart,
+    /// `None` for an anonymous customer.
+    pub customer_id: Option<String>,
     pub customer_groups: Vec<String>,
+    /// Lifetime order count, used when a history condition has no timeframe.
     pub order_count: i32,
+    /// The customer's orders, at least as far back as the longest timeframe
+    /// any history condition uses.
+    pub order_history: Vec<OrderRecord>,
     pub now: DateTime<Utc>,
     pub is_first_purchase: bool,
-    pub current_day: u8,
-    pub current_hour: i32,
+    /// The shop's IANA time zone; schedule conditions read `now` in it.
+    pub timezone: Tz,
     pub applied_coupon: Option<Coupon>,
     pub customer_membership: Option<Membership>,
+    /// The customer's redemption ledger entries in the shop.
+    pub redemptions: Vec<Redemption>,
+}
+
+impl EvaluationContext {
+    /// Builds a context for an anonymous customer with no history, taking the
+    /// shop from the cart. The shop's time zone defaults to UTC.
+    pub fn new(cart: Cart, now: DateTime<Utc>) -> Self {
+        Self {
+            shop_id: cart.shop_id.clone(),
+            cart,
+            customer_id: None,
+            customer_groups: Vec::new(),
+            order_count: 0,
+            order_history: Vec::new(),
+            now,
+            is_first_purchase: false,
+            timezone: Tz::UTC,
+            applied_coupon: None,
+            customer_membership: None,
+            redemptions: Vec::new(),
+        }
+    }
+
+    /// Identifies the customer, along with their ledger entries in the shop.
+    pub fn with_customer(
+        mut self,
+        customer_id: impl Into<String>,
+        redemptions: Vec<Redemption>,
+    ) -> Self {
+        self.customer_id = Some(customer_id.into());
+        self.redemptions = redemptions;
+        self
+    }
+
+    pub fn with_timezone(mut self, timezone: Tz) -> Self {
+        self.timezone = timezone;
+        self
+    }
+
+    pub fn cart_total(&self) -> Money {
+        Money::new(self.cart.subtotal(), self.cart.currency)
+    }
+
+    pub fn local_now(&self) -> DateTime<Tz> {
+        self.now.with_timezone(&self.timezone)
+    }
+
+    /// Local day of the week, 0 for Sunday through 6 for Saturday.
+    pub fn local_weekday(&self) -> u8 {
+        self.local_now().weekday().num_days_from_sunday() as u8
+    }
+
+    /// Minutes since local midnight.
+    pub fn local_minute_of_day(&self) -> i32 {
+        let local = self.local_now();
+        (local.hour() * 60 + local.minute()) as i32
+    }
+
+    /// Orders placed in the `days` days up to `now`; all of them when `days`
+    /// is not positive.
+    pub fn orders_within(&self, days: i32) -> impl Iterator<Item = &OrderRecord> {
+        let since = (days > 0).then(|| self.now - Duration::days(i64::from(days)));
+        self.order_history.iter().filter(move |order| {
+            order.placed_at <= self.now && since.is_none_or(|since| order.placed_at > since)
+        })
+    }
+
+    /// Number of orders in the last `days` days, or the lifetime count when
+    /// `days` is not positive.
+    pub fn order_count_within(&self, days: i32) -> i32 {
+        if days > 0 {
+            self.orders_within(days).count() as i32
+        } else {
+            self.order_count
+        }
+    }
+
+    /// What the orders in `currency` placed in the last `days` days add up
+    /// to; all of them when `days` is not positive.
+    pub fn spend_within(&self, days: i32, currency: Currency) -> Money {
+        let spent = self
+            .orders_within(days)
+            .filter(|order| order.amount.currency == currency)
+            .map(|order| order.amount.value())
+            .sum();
+        Money::new(spent, currency)
+    }
+
+    /// How often the customer has redeemed the rule or coupon `entity_id`;
+    /// `None` for an anonymous customer.
+    pub fn redemptions_of(&self, entity: Entity, entity_id: &str) -> Option<i32> {
+        self.customer_id.as_ref()?;
+        Some(
+            self.redemptions
+                .iter()
+                .filter(|r| r.key.entity == entity && r.key.entity_id == entity_id)
+                .map(|r| r.count)
+                .sum(),
+        )
+    }
+
+    /// Whether the customer may use `entity_id` again under a per-customer
+    /// `limit`. Anonymous customers only pass when there is no limit.
+    pub fn within_customer_limit(
+        &self,
+        entity: Entity,
+        entity_id: &str,
+        limit: Option<i32>,
+    ) -> bool {
+        limit.is_none_or(|max| {
+            self.redemptions_of(entity, entity_id)
+                .is_some_and(|used| used < max)
+        })
+    }
 }
 
 impl DiscountRule {
-    pub fn evaluate(&self, ctx: &EvaluationContext) -> bool {
-        if !self.is_active {
-            return false;
+    /// Active and inside its date window at `now`. Conditions are not
+    /// checked, since they need a full evaluation context.
+    pub fn is_live_at(&self, now: DateTime<Utc>) -> bool {
+        self.is_active
+            && self.start_date.is_none_or(|start| now >= start)
+            && self.end_date.is_none_or(|end| now <= end)
+    }
+
+    /// A currency other than `currency` that the rule's amounts are in, if
+    /// there is one.
+    pub fn foreign_currency(&self, currency: Currency) -> Option<Currency> {
+        let mut found = Vec::new();
+        for condition in &self.conditions {
+            condition.currencies(&mut found);
         }
-        if let Some(start) = self.start_date {
-            if ctx.now < start {
-                return false;
+        for action in &self.actions {
+            match action {
+                DiscountAction::FixedAmountOff { amount } => found.push(amount.currency),
+                DiscountAction::BundlePrice { price, .. } => found.push(price.currency),
+                DiscountAction::QuantityTiers {
+                    value_type: TierValueType::FixedAmount,
+                    currency,
+                    ..
+                } => found.push(*currency),
+                DiscountAction::SpendTiers {
+                    tiers, currency, ..
+                } => {
+                    found.push(*currency);
+                    found.extend(tiers.iter().map(|t| t.min_spend.currency));
+                }
+                _ => {}
             }
         }
-        if let Some(end) = self.end_date {
-            if ctx.now > end {
-                return false;
+        found.into_iter().find(|c| *c != currency)
+    }
+
+    /// Fails when the rule's amounts are not all in `currency`.
+    pub fn check_currency(&self, currency: Currency) -> Result<(), ServiceError> {
+        match self.foreign_currency(currency) {
+            Some(found) => Err(ServiceError::CurrencyMismatch {
+                entity: Entity::DiscountRule,
+                key: self.id.clone(),
+                currency: found,
+                expected: currency,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether another use fits under `max_usage`.
+    pub fn has_uses_left(&self) -> bool {
+        self.max_usage.is_none_or(|max| self.usage_count < max)
+    }
+
+    /// Live at `now` and, if the rule recurs, inside one of its windows in
+    /// `timezone`.
+    pub fn is_scheduled_at(&self, now: DateTime<Utc>, timezone: Tz) -> bool {
+        self.is_live_at(now)
+            && self
+                .recurrence
+                .as_ref()
+                .is_none_or(|r| r.is_active_at(now, timezone))
+    }
+
+    /// Up to `count` periods from `after` onwards in which the rule is live,
+    /// clipped to its date window. A rule without a recurrence has a single
+    /// window running to its end date.
+    pub fn next_activation_windows(
+        &self,
+        after: DateTime<Utc>,
+        timezone: Tz,
+        count: usize,
+    ) -> Vec<ActivationWindow> {
+        if !self.is_active || self.end_date.is_some_and(|end| end < after) {
+            return Vec::new();
+        }
+        let from = self.start_date.map_or(after, |start| start.max(after));
+        let windows = match &self.recurrence {
+            Some(recurrence) => recurrence.windows_after(from, timezone, count),
+            None => vec![ActivationWindow {
+                starts_at: from,
+                ends_at: None,
+            }],
+        };
+        windows
+            .into_iter()
+            .take_while(|w| self.end_date.is_none_or(|end| w.starts_at <= end))
+            .map(|w| ActivationWindow {
+                starts_at: self.start_date.map_or(w.starts_at, |s| s.max(w.starts_at)),
+                ends_at: match (w.ends_at, self.end_date) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                },
+            })
+            .take(count)
+            .collect()
+    }
+
+    /// Checks the fields a stored rule must have before it can be saved.
+    pub fn validate(&self) -> Result<(), ServiceError> {
+        let mut fields = Vec::new();
+        if self.shop_id.trim().is_empty() {
+            fields.push(FieldError::new("shop_id", "must not be empty"));
+        }
+        if self.name.trim().is_empty() {
+            fields.push(FieldError::new("name", "must not be empty"));
+        }
+        if self.code.as_ref().is_some_and(|c| c.trim().is_empty()) {
+            fields.push(FieldError::new("code", "must not be empty when set"));
+        }
+        if self.usage_count < 0 {
+            fields.push(FieldError::new("usage_count", "must not be negative"));
+        }
+        if self.max_usage.is_some_and(|max| max < 0) {
+            fields.push(FieldError::new("max_usage", "must not be negative"));
+        }
+        if self.max_usage_per_customer.is_some_and(|max| max < 0) {
+            fields.push(FieldError::new(
+                "max_usage_per_customer",
+                "must not be negative",
+            ));
+        }
+        if let (Some(start), Some(end)) = (self.start_date, self.end_date)
+            && start > end
+        {
+            fields.push(FieldError::new("end_date", "must not be before start_date"));
+        }
+        if let Some(recurrence) = &self.recurrence {
+            fields.extend(recurrence.field_errors());
+        }
+        for (i, action) in self.actions.iter().enumerate() {
+            let field = format!("actions[{i}]");
+            match action {
+                DiscountAction::PercentageOff { percent } => match decimal::from_bson(*percent) {
+                    Ok(p) if p >= Decimal::ZERO && p <= Decimal::ONE_HUNDRED => {}
+                    _ => fields.push(FieldError::new(field, "percent must be between 0 and 100")),
+                },
+                DiscountAction::FixedAmountOff { amount } => {
+                    match decimal::from_bson(amount.amount) {
+                        Ok(a) if a >= Decimal::ZERO => {}
+                        _ => fields.push(FieldError::new(field, "amount must not be negative")),
+                    }
+                }
+                DiscountAction::FreeShipping => {}
+                DiscountAction::BuyXGetY {
+                    buy_quantity,
+                    get_quantity,
+                    get_percent_off,
+                    max_applications,
+                    ..
+                } => {
+                    if *buy_quantity <= 0 || *get_quantity <= 0 {
+                        fields.push(FieldError::new(&field, "quantities must be positive"));
+                    }
+                    match decimal::from_bson(*get_percent_off) {
+                        Ok(p) if p > Decimal::ZERO && p <= Decimal::ONE_HUNDRED => {}
+                        _ => fields.push(FieldError::new(
+                            &field,
+                            "get_percent_off must be above 0 and at most 100",
+                        )),
+                    }
+                    if max_applications.is_some_and(|max| max <= 0) {
+                        fields.push(FieldError::new(
+                            field,
+                            "max_applications must be positive when set",
+                        ));
+                    }
+                }
+                DiscountAction::QuantityTiers {
+                    value_type, tiers, ..
+                } => {
+                    let thresholds: Vec<_> = tiers
+                        .iter()
+                        .map(|t| Decimal::from(t.min_quantity))
+                        .collect();
+                    if tiers.iter().any(|t| t.min_quantity <= 0) {
+                        fields.push(FieldError::new(&field, "min_quantity must be positive"));
+                    }
+                    tier_errors(
+                        &mut fields,
+                        &field,
+                        *value_type,
+                        &thresholds,
+                        tiers.iter().map(|t| t.value),
+                    );
+                }
+                DiscountAction::BundlePrice {
+                    components,
+                    price,
+                    max_applications,
+                } => {
+                    let mut products: Vec<&str> =
+                        components.iter().map(|c| c.product_id.as_str()).collect();
+                    products.sort_unstable();
+                    products.dedup();
+                    if components.is_empty()
+                        || products.len() != components.len()
+                        || components.iter().any(|c| c.quantity <= 0)
+                    {
+                        fields.push(FieldError::new(
+                            &field,
+                            "components must list distinct products with positive quantities",
+                        ));
+                    }
+                    if !decimal::from_bson(price.amount).is_ok_and(|p| p >= Decimal::ZERO) {
+                        fields.push(FieldError::new(&field, "price must not be negative"));
+                    }
+                    if max_applications.is_some_and(|max| max <= 0) {
+                        fields.push(FieldError::new(
+                            field,
+                            "max_applications must be positive when set",
+                        ));
+                    }
+                }
+                DiscountAction::SpendTiers {
+                    value_type,
+                    tiers,
+                    currency,
+                    ..
+                } => {
+                    let thresholds: Vec<_> = tiers
+                        .iter()
+                        .map(|t| {
+                            decimal::from_bson(t.min_spend.amount).unwrap_or(Decimal::NEGATIVE_ONE)
+                        })
+                        .collect();
+                    if thresholds.iter().any(|t| *t < Decimal::ZERO) {
+                        fields.push(FieldError::new(&field, "min_spend must not be negative"));
+                    }
+                    if tiers.iter().any(|t| t.min_spend.currency != *currency) {
+                        fields.push(FieldError::new(
+                            &field,
+                            "min_spend must be in the action's currency",
+                        ));
+                    }
+                    tier_errors(
+                        &mut fields,
+                        &field,
+                        *value_type,
+                        &thresholds,
+                        tiers.iter().map(|t| t.value),
+                    );
+                }
             }
         }
+        ServiceError::check_fields(fields)
+    }
+
+    pub fn evaluate(&self, ctx: &EvaluationContext) -> bool {
+        if !self.is_scheduled_at(ctx.now, ctx.timezone)
+            || !self.has_uses_left()
+            || !ctx.within_customer_limit(
+                Entity::DiscountRule,
+                &self.id,
+                self.max_usage_per_customer,
+            )
+        {
+            return false;
+        }
         self.conditions.iter().all(|cond| cond.evaluate(ctx))
     }
 }
 
 impl Condition {
+    /// Adds the currencies of the amounts in this condition and the ones it
+    /// nests to `found`.
+    fn currencies(&self, found: &mut Vec<Currency>) {
+        match self {
+            Condition::CartTotal { value: amount, .. }
+            | Condition::MinimumSpend { amount }
+            | Condition::SpendHistory {
+                min_amount: amount, ..
+            } => found.push(amount.currency),
+            Condition::All { conditions } | Condition::Any { conditions } => {
+                for condition in conditions {
+                    condition.currencies(found);
+                }
+            }
+            Condition::Not { condition } => condition.currencies(found),
+            _ => {}
+        }
+    }
+
     pub fn evaluate(&self, ctx: &EvaluationContext) -> bool {
         match self {
             Condition::CartTotal { operator, value } => {
-                compare_decimal(ctx.cart_total, *value, operator)
+                compare_money(&ctx.cart_total(), value, operator)
             }
             Condition::ProductQuantity {
                 product_id,
                 operator,
                 quantity,
-            } => {
-                let qty = *ctx.product_quantities.get(product_id).unwrap_or(&0);
-                compare_i32(qty, *quantity, operator)
-            }
+            } => compare_i32(ctx.cart.quantity_of(product_id), *quantity, operator),
             Condition::CustomerGroup { group_ids } => {
                 group_ids.iter().any(|g| ctx.customer_groups.contains(g))
             }
             Condition::PurchaseHistory {
                 min_orders,
-                timeframe_days: _,
-            } => ctx.order_count >= *min_orders,
+                timeframe_days,
+            } => ctx.order_count_within(*timeframe_days) >= *min_orders,
+            Condition::SpendHistory {
+                min_amount,
+                timeframe_days,
+            } => compare_money(
+                &ctx.spend_within(*timeframe_days, min_amount.currency),
+                min_amount,
+                &Operator::GreaterThanOrEqual,
+            ),
             Condition::TimeOfDay {
                 start_hour,
+                start_minute,
                 end_hour,
-            } => ctx.current_hour >= *start_hour && ctx.current_hour <= *end_hour,
-            Condition::DayOfWeek { days } => days.contains(&ctx.current_day),
-            Condition::FirstPurchase => ctx.is_first_purchase,
-            Condition::ProductCategory { category_ids } => {
-                ctx.product_quantities
-                    .iter()
-                    .any(|(product_id, &quantity)| {
-                        if quantity > 0 {
-                            if let Some(product_categories) = ctx.product_categories.get(product_id)
-                            {
-                                category_ids
-                                    .iter()
-                                    .any(|cat_id| product_categories.contains(cat_id))
-                            } else {
-                                false
-                            }
-                        } else {
-                            false
-                        }
-                    })
+                end_minute,
+            } => {
+                let start = start_hour * 60 + start_minute;
+                let end = end_hour * 60 + end_minute;
+                let now = ctx.local_minute_of_day();
+                if start <= end {
+                    now >= start && now <= end
+                } else {
+                    now >= start || now <= end
+                }
             }
+            Condition::DayOfWeek { days } => days.contains(&ctx.local_weekday()),
+            Condition::FirstPurchase => ctx.is_first_purchase,
+            Condition::ProductCategory { category_ids } => ctx
+                .cart
+                .lines
+                .iter()
+                .any(|line| line.quantity > 0 && line.in_any_category(category_ids)),
             Condition::Coupon { code } => {
                 if let Some(coupon) = &ctx.applied_coupon {
-                    coupon.code == *code
-                        && coupon.expires_at.map_or(true, |exp| ctx.now <= exp)
-                        && coupon.max_uses.map_or(true, |max| coupon.used_count < max)
+                    coupon_code::normalize(&coupon.code) == coupon_code::normalize(code)
+                        && coupon.check(ctx).is_ok()
                 } else {
                     false
                 }
             }
             Condition::MinimumSpend { amount } => {
-                compare_decimal(ctx.cart_total, *amount, &Operator::GreaterThanOrEqual)
+                compare_money(&ctx.cart_total(), amount, &Operator::GreaterThanOrEqual)
             }
             Condition::MembershipTier { tiers } => {
                 if let Some(membership) = &ctx.customer_membership {
@@ -204,6 +838,7 @@ impl Condition {
                     false
                 }
             }
+            Condition::Bundle { components } => bundle_count(&ctx.cart, components) > 0,
             Condition::MembershipActive => {
                 if let Some(membership) = &ctx.customer_membership {
                     membership.is_valid_at(ctx.now)
@@ -211,24 +846,63 @@ impl Condition {
                     false
                 }
             }
+            Condition::All { conditions } => conditions.iter().all(|c| c.evaluate(ctx)),
+            Condition::Any { conditions } => conditions.iter().any(|c| c.evaluate(ctx)),
+            Condition::Not { condition } => !condition.evaluate(ctx),
         }
     }
 }
 
+/// Checks the tiers of a tiered action, given their thresholds in order.
+fn tier_errors(
+    fields: &mut Vec<FieldError>,
+    field: &str,
+    value_type: TierValueType,
+    thresholds: &[Decimal],
+    values: impl Iterator<Item = Decimal128>,
+) {
+    if thresholds.is_empty() {
+        fields.push(FieldError::new(field, "tiers must not be empty"));
+    }
+    if thresholds.windows(2).any(|pair| pair[0] >= pair[1]) {
+        fields.push(FieldError::new(
+            field,
+            "tier thresholds must be strictly ascending",
+        ));
+    }
+    let valid = |value: Decimal| match value_type {
+        TierValueType::Percentage => value >= Decimal::ZERO && value <= Decimal::ONE_HUNDRED,
+        TierValueType::FixedAmount => value >= Decimal::ZERO,
+    };
+    if !values
+        .map(decimal::from_bson)
+        .all(|value| value.is_ok_and(valid))
+    {
+        fields.push(FieldError::new(
+            field,
+            match value_type {
+                TierValueType::Percentage => "tier values must be percentages between 0 and 100",
+                TierValueType::FixedAmount => "tier values must not be negative",
+            },
+        ));
+    }
+}
+
+/// Exact comparison of two `Decimal128` values. NaN, infinities and values
+/// that cannot be represented exactly never satisfy any operator.
 pub fn compare_decimal(a: Decimal128, b: Decimal128, op: &Operator) -> bool {
-    let a_f64 = a.to_string().parse::<f64>().unwrap_or(0.0);
-    let b_f64 = b.to_string().parse::<f64>().unwrap_or(0.0);
-    match op {
-        Operator::Equal => a_f64 == b_f64,
-        Operator::NotEqual => a_f64 != b_f64,
-        Operator::GreaterThan => a_f64 > b_f64,
-        Operator::LessThan => a_f64 < b_f64,
-        Operator::GreaterThanOrEqual => a_f64 >= b_f64,
-        Operator::LessThanOrEqual => a_f64 <= b_f64,
+    match (decimal::from_bson(a), decimal::from_bson(b)) {
+        (Ok(a), Ok(b)) => decimal::compare(a, b, op),
         _ => false,
     }
 }
 
+/// Amounts in different currencies never satisfy any operator.
+fn compare_money(a: &Money, b: &Money, op: &Operator) -> bool {
+    a.currency == b.currency
+        && decimal::from_bson(b.amount).is_ok_and(|b| decimal::compare(a.value(), b, op))
+}
+
 pub fn compare_i32(a: i32, b: i32, op: &Operator) -> bool {
     match op {
         Operator::Equal => a == b,
@@ -242,19 +916,509 @@ pub fn compare_i32(a: i32, b: i32, op: &Operator) -> bool {
 }
 #[tarpc::service]
 pub trait DiscountService {
-    async fn create_discount_rule(rule: DiscountRule) -> Result<DiscountRule, String>;
-    async fn get_discount_rule(id: String) -> Result<DiscountRule, String>;
-    async fn update_discount_rule(rule: DiscountRule) -> Result<DiscountRule, String>;
-    async fn delete_discount_rule(id: String) -> Result<(), String>;
-    async fn list_discount_rules(shop_id: String) -> Result<Vec<DiscountRule>, String>;
+    async fn create_discount_rule(rule: DiscountRule) -> Result<DiscountRule, ServiceError>;
+    async fn get_discount_rule(id: String) -> Result<DiscountRule, ServiceError>;
+    async fn update_discount_rule(rule: DiscountRule) -> Result<DiscountRule, ServiceError>;
+    async fn delete_discount_rule(id: String) -> Result<(), ServiceError>;
+    async fn list_discount_rules(shop_id: String) -> Result<Vec<DiscountRule>, ServiceError>;
+    /// The total left after the rule's cart-wide actions. Fails when the
+    /// rule's amounts are in another currency than `cart_total`, or when it
+    /// recurs and now is outside its windows in `timezone` (an IANA name
+    /// such as `Europe/Paris`).
     async fn apply_discount_rule(
         rule_id: String,
-        cart_total: Decimal128,
-    ) -> Result<Decimal128, String>;
-    async fn validate_discount_rule(rule_id: String) -> Result<bool, String>;
-    async fn get_discount_rule_by_code(rule_code: String) -> Result<DiscountRule, String>;
+        timezone: String,
+        cart_total: Money,
+    ) -> Result<Money, ServiceError>;
+    /// Whether the rule can be used now, reading its recurrence in
+    /// `timezone`.
+    async fn validate_discount_rule(
+        rule_id: String,
+        timezone: String,
+    ) -> Result<bool, ServiceError>;
+    /// The next `count` periods in which the rule is live, reading its
+    /// recurrence in `timezone` (an IANA name such as `Europe/Paris`).
+    async fn list_activation_windows(
+        rule_id: String,
+        timezone: String,
+        count: u32,
+    ) -> Result<Vec<ActivationWindow>, ServiceError>;
+    async fn get_discount_rule_by_code(rule_code: String) -> Result<DiscountRule, ServiceError>;
+    /// Records one use of each rule applied to a committed order, also
+    /// against the customer's own limit when `customer_id` is given. Either
+    /// every rule is counted or, if one is missing, inactive or used up,
+    /// none is.
+    async fn redeem_discount_rules(
+        customer_id: Option<String>,
+        rule_ids: Vec<String>,
+    ) -> Result<Vec<DiscountRule>, ServiceError>;
+    /// Gives back the uses recorded for an order that was cancelled.
+    async fn release_discount_rules(
+        customer_id: Option<String>,
+        rule_ids: Vec<String>,
+    ) -> Result<Vec<DiscountRule>, ServiceError>;
     async fn get_discount_rule_by_code_and_shop(
         rule_code: String,
         shop_id: String,
-    ) -> Result<DiscountRule, String>;
+    ) -> Result<DiscountRule, ServiceError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{self, cart, ctx, d128, line, now, usd},
+        redemption::RedemptionKey,
+    };
+
+    fn round_trip(rule: &DiscountRule) -> DiscountRule {
+        bson::from_document(bson::to_document(rule).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_undated_rule_round_trips_through_bson() {
+        let stored = round_trip(&fixtures::rule("rule-1", Vec::new()));
+        assert_eq!(stored.start_date, None);
+        assert_eq!(stored.end_date, None);
+    }
+
+    #[test]
+    fn test_dated_rule_round_trips_through_bson() {
+        let mut rule = fixtures::rule("rule-1", Vec::new());
+        rule.start_date = Some(rule.created_at);
+        rule.end_date = Some(rule.created_at + Duration::days(7));
+        let stored = round_trip(&rule);
+        assert_eq!(stored.start_date, rule.start_date);
+        assert_eq!(stored.end_date, rule.end_date);
+    }
+
+    #[test]
+    fn test_foreign_currency_covers_tier_and_bundle_amounts() {
+        let mut rule = fixtures::rule("rule-1", Vec::new());
+        rule.actions = vec![DiscountAction::BundlePrice {
+            components: vec![BundleComponent::new("a", 2)],
+            price: Money::new(Decimal::TEN, Currency::JPY),
+            max_applications: None,
+        }];
+        assert_eq!(rule.foreign_currency(Currency::USD), Some(Currency::JPY));
+
+        rule.actions = vec![DiscountAction::SpendTiers {
+            items: ItemSelector::default(),
+            value_type: TierValueType::Percentage,
+            tiers: vec![SpendTier {
+                min_spend: Money::new(Decimal::ONE_HUNDRED, Currency::JPY),
+                value: decimal::to_bson(Decimal::TEN),
+            }],
+            mode: TierMode::WholeAtReachedTier,
+            currency: Currency::JPY,
+        }];
+        assert_eq!(rule.foreign_currency(Currency::USD), Some(Currency::JPY));
+        assert_eq!(rule.foreign_currency(Currency::JPY), None);
+
+        rule.actions = vec![DiscountAction::QuantityTiers {
+            items: ItemSelector::default(),
+            value_type: TierValueType::FixedAmount,
+            tiers: vec![QuantityTier {
+                min_quantity: 2,
+                value: decimal::to_bson(Decimal::ONE),
+            }],
+            mode: TierMode::Graduated,
+            currency: Currency::JPY,
+        }];
+        assert_eq!(rule.foreign_currency(Currency::USD), Some(Currency::JPY));
+
+        rule.actions = Vec::new();
+        rule.conditions = vec![Condition::SpendHistory {
+            min_amount: Money::new(Decimal::ONE_HUNDRED, Currency::JPY),
+            timeframe_days: 30,
+        }];
+        assert_eq!(rule.foreign_currency(Currency::USD), Some(Currency::JPY));
+    }
+
+    #[test]
+    fn test_legacy_bare_amounts_read_as_us_dollars() {
+        let price = decimal::to_bson(Decimal::TEN);
+        let action: DiscountAction = bson::from_document(bson::doc! {
+            "BundlePrice": { "components": [], "price": price }
+        })
+        .unwrap();
+        let DiscountAction::BundlePrice { price, .. } = action else {
+            panic!("expected a bundle price, got {action:?}");
+        };
+        assert_eq!(price, Money::new(Decimal::TEN, Currency::USD));
+
+        let tier: SpendTier =
+            bson::from_document(bson::doc! { "min_spend": price.amount, "value": price.amount })
+                .unwrap();
+        assert_eq!(tier.min_spend.currency, Currency::USD);
+
+        let order: OrderRecord = bson::from_document(bson::doc! {
+            "order_id": "order-1",
+            "placed_at": bson::DateTime::now(),
+            "amount": price.amount,
+        })
+        .unwrap();
+        assert_eq!(order.amount.currency, Currency::USD);
+    }
+
+    #[test]
+    fn test_spend_thresholds_must_be_in_the_action_currency() {
+        let mut rule = fixtures::rule("rule-1", Vec::new());
+        rule.actions = vec![DiscountAction::SpendTiers {
+            items: ItemSelector::default(),
+            value_type: TierValueType::FixedAmount,
+            tiers: vec![SpendTier {
+                min_spend: Money::new(Decimal::ONE_HUNDRED, Currency::USD),
+                value: decimal::to_bson(Decimal::TEN),
+            }],
+            mode: TierMode::WholeAtReachedTier,
+            currency: Currency::JPY,
+        }];
+        assert!(rule.validate().is_err());
+    }
+
+    #[test]
+    fn test_context_is_derived_from_the_cart() {
+        let ctx = ctx(cart(vec![
+            line("a", "p-1", "5", 2),
+            line("b", "p-1", "5", 1)
+                .with_variant("red")
+                .with_categories(vec!["shoes".to_string()]),
+            line("c", "p-2", "10", 0).with_categories(vec!["hats".to_string()]),
+        ]));
+        assert_eq!(ctx.shop_id, "shop-1");
+        assert_eq!(ctx.cart_total(), usd("15"));
+        assert_eq!(ctx.local_weekday(), 2);
+        assert_eq!(ctx.local_minute_of_day(), 22 * 60 + 13);
+        assert_eq!(ctx.now, now());
+
+        let holds = |condition: Condition| condition.evaluate(&ctx);
+        assert!(holds(Condition::ProductQuantity {
+            product_id: "p-1".to_string(),
+            operator: Operator::Equal,
+            quantity: 3,
+        }));
+        assert!(holds(Condition::ProductCategory {
+            category_ids: vec!["shoes".to_string()],
+        }));
+        assert!(!holds(Condition::ProductCategory {
+            category_ids: vec!["hats".to_string()],
+        }));
+        assert!(holds(Condition::MinimumSpend { amount: usd("15") }));
+    }
+
+    fn at(hour: u32, minute: u32) -> EvaluationContext {
+        // Wednesday 2024-01-10 in UTC.
+        let now = DateTime::from_naive_utc_and_offset(
+            chrono::NaiveDate::from_ymd_opt(2024, 1, 10)
+                .unwrap()
+                .and_hms_opt(hour, minute, 0)
+                .unwrap(),
+            Utc,
+        );
+        EvaluationContext::new(cart(vec![line("a", "a", "10", 1)]), now)
+    }
+
+    fn late_night() -> Condition {
+        Condition::TimeOfDay {
+            start_hour: 22,
+            start_minute: 0,
+            end_hour: 1,
+            end_minute: 59,
+        }
+    }
+
+    #[test]
+    fn test_overnight_time_of_day_wraps_midnight() {
+        assert!(late_night().evaluate(&at(23, 30)));
+        assert!(late_night().evaluate(&at(1, 59)));
+        assert!(!late_night().evaluate(&at(2, 0)));
+        assert!(!late_night().evaluate(&at(21, 59)));
+    }
+
+    #[test]
+    fn test_schedule_conditions_read_the_shop_time_zone() {
+        let tokyo = at(16, 0).with_timezone(chrono_tz::Asia::Tokyo);
+        assert_eq!(tokyo.local_minute_of_day(), 60);
+        assert!(late_night().evaluate(&tokyo));
+        assert!(!late_night().evaluate(&at(16, 0)));
+        let thursday = Condition::DayOfWeek { days: vec![4] };
+        assert!(thursday.evaluate(&tokyo));
+        assert!(!thursday.evaluate(&at(16, 0)));
+    }
+
+    #[test]
+    fn test_legacy_time_of_day_runs_to_the_end_of_the_hour() {
+        let condition: Condition =
+            bson::from_document(bson::doc! { "TimeOfDay": { "start_hour": 9, "end_hour": 17 } })
+                .unwrap();
+        assert!(condition.evaluate(&at(17, 59)));
+        assert!(!condition.evaluate(&at(18, 0)));
+    }
+
+    #[test]
+    fn test_nested_conditions_combine() {
+        let big_cart = Condition::CartTotal {
+            operator: Operator::GreaterThan,
+            value: usd("50"),
+        };
+        let any = Condition::Any {
+            conditions: vec![big_cart.clone(), late_night()],
+        };
+        let not_big = Condition::Not {
+            condition: Box::new(big_cart),
+        };
+        assert!(any.evaluate(&at(23, 0)));
+        assert!(!any.evaluate(&at(12, 0)));
+        assert!(not_big.evaluate(&at(12, 0)));
+        assert!(Condition::All { conditions: vec![] }.evaluate(&at(12, 0)));
+        assert!(!Condition::Any { conditions: vec![] }.evaluate(&at(12, 0)));
+    }
+
+    #[test]
+    fn test_condition_trees_round_trip_through_bson() {
+        let tree = Condition::All {
+            conditions: vec![
+                Condition::Not {
+                    condition: Box::new(Condition::FirstPurchase),
+                },
+                Condition::Any {
+                    conditions: vec![
+                        Condition::CustomerGroup {
+                            group_ids: vec!["vip".to_string()],
+                        },
+                        late_night(),
+                    ],
+                },
+            ],
+        };
+        let stored: Condition = bson::from_bson(bson::to_bson(&tree).unwrap()).unwrap();
+        assert_eq!(format!("{stored:?}"), format!("{tree:?}"));
+        assert!(stored.evaluate(&at(23, 0)));
+        assert!(!stored.evaluate(&at(9, 0)));
+    }
+
+    #[test]
+    fn test_live_window_includes_its_ends() {
+        let mut rule = fixtures::rule("rule-1", Vec::new());
+        rule.start_date = Some(now());
+        rule.end_date = Some(now() + chrono::Duration::days(1));
+        assert!(!rule.is_live_at(now() - chrono::Duration::seconds(1)));
+        assert!(rule.is_live_at(now()));
+        assert!(rule.is_live_at(now() + chrono::Duration::days(1)));
+        assert!(!rule.is_live_at(now() + chrono::Duration::days(2)));
+        rule.is_active = false;
+        assert!(!rule.is_live_at(now()));
+    }
+
+    #[test]
+    fn test_validate_reports_every_bad_field() {
+        let mut rule = fixtures::rule(
+            "rule-1",
+            vec![
+                DiscountAction::PercentageOff {
+                    percent: d128("150"),
+                },
+                DiscountAction::FixedAmountOff { amount: usd("-5") },
+            ],
+        );
+        rule.name = " ".to_string();
+        let Err(ServiceError::ValidationFailed { fields }) = rule.validate() else {
+            panic!("expected validation to fail");
+        };
+        let fields: Vec<&str> = fields.iter().map(|f| f.field.as_str()).collect();
+        assert_eq!(fields, vec!["name", "actions[0]", "actions[1]"]);
+    }
+
+    #[test]
+    fn test_history_conditions_count_only_their_timeframe() {
+        let mut ctx = ctx(cart(vec![line("a", "a", "10", 1)]));
+        let order = |days_ago: i64, amount: &str| OrderRecord {
+            order_id: format!("order-{days_ago}"),
+            placed_at: now() - Duration::days(days_ago),
+            amount: usd(amount),
+        };
+        ctx.order_history = vec![
+            order(1, "50"),
+            order(10, "30"),
+            order(40, "100"),
+            order(-1, "999"),
+        ];
+        ctx.order_count = 5;
+
+        let orders = |min_orders, timeframe_days| {
+            Condition::PurchaseHistory {
+                min_orders,
+                timeframe_days,
+            }
+            .evaluate(&ctx)
+        };
+        assert!(!orders(2, 7));
+        assert!(orders(2, 30));
+        assert!(!orders(3, 30));
+        assert!(orders(5, 0));
+
+        // Orders in another currency are left out of the dollar spend.
+        ctx.order_history.push(OrderRecord {
+            amount: Money::new(Decimal::from(5000), Currency::JPY),
+            ..order(2, "0")
+        });
+        let spent = |min_amount: &str, timeframe_days| {
+            Condition::SpendHistory {
+                min_amount: usd(min_amount),
+                timeframe_days,
+            }
+            .evaluate(&ctx)
+        };
+        assert!(spent("80", 30));
+        assert!(!spent("80", 7));
+        assert!(spent("180", 0));
+        assert!(!spent("181", 0));
+        let spent_yen = Condition::SpendHistory {
+            min_amount: Money::new(Decimal::from(5000), Currency::JPY),
+            timeframe_days: 7,
+        };
+        assert!(spent_yen.evaluate(&ctx));
+    }
+
+    #[test]
+    fn test_recurring_rule_applies_only_inside_its_windows() {
+        // Wednesdays from 12:00 for an hour, the rule ending halfway
+        // through the third.
+        let week = |weeks: i64| at(12, 0).now + Duration::weeks(weeks);
+        let mut rule = fixtures::rule("rule-1", Vec::new());
+        rule.end_date = Some(week(2) + Duration::minutes(30));
+        rule.recurrence = Some(
+            bson::from_document(bson::doc! {
+                "frequency": "Weekly",
+                "starts_on": "2024-01-10",
+                "start_hour": 12,
+                "duration_minutes": 60,
+            })
+            .unwrap(),
+        );
+        assert!(rule.evaluate(&at(12, 30)));
+        assert!(!rule.evaluate(&at(13, 0)));
+
+        let starts: Vec<_> = rule
+            .next_activation_windows(at(14, 0).now, Tz::UTC, 5)
+            .into_iter()
+            .map(|w| (w.starts_at, w.ends_at))
+            .collect();
+        assert_eq!(
+            starts,
+            vec![
+                (week(1), Some(week(1) + Duration::hours(1))),
+                (week(2), rule.end_date),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_usage_limits_stop_the_rule() {
+        let mut rule = fixtures::rule("rule-1", Vec::new());
+        rule.max_usage = Some(2);
+        rule.usage_count = 2;
+        assert!(!rule.evaluate(&at(12, 0)));
+        rule.usage_count = 1;
+        assert!(rule.evaluate(&at(12, 0)));
+    }
+
+    #[test]
+    fn test_per_customer_limit_reads_the_ledger() {
+        let mut rule = fixtures::rule("rule-1", Vec::new());
+        rule.max_usage_per_customer = Some(1);
+        let anonymous = at(12, 0);
+        assert!(!rule.evaluate(&anonymous));
+
+        let redemption = |count| Redemption {
+            key: RedemptionKey::new("shop-1", "customer-1", Entity::DiscountRule, "rule-1"),
+            count,
+            updated_at: anonymous.now,
+        };
+        let fresh = at(12, 0).with_customer("customer-1", vec![redemption(0)]);
+        assert!(rule.evaluate(&fresh));
+        let used = at(12, 0).with_customer("customer-1", vec![redemption(1)]);
+        assert!(!rule.evaluate(&used));
+    }
+
+    #[test]
+    fn test_legacy_buy_x_get_y_reads_as_single_product_selectors() {
+        let action: DiscountAction = bson::from_document(bson::doc! {
+            "BuyXGetY": {
+                "buy_product_id": "shirt",
+                "buy_quantity": 2,
+                "get_product_id": "socks",
+                "get_quantity": 1,
+            }
+        })
+        .unwrap();
+        let DiscountAction::BuyXGetY {
+            buy,
+            get,
+            get_percent_off,
+            max_applications,
+            ..
+        } = action
+        else {
+            panic!("read {action:?}");
+        };
+        assert_eq!(buy, ItemSelector::products(vec!["shirt".to_string()]));
+        assert_eq!(get, ItemSelector::products(vec!["socks".to_string()]));
+        assert_eq!(
+            decimal::from_bson(get_percent_off),
+            Ok(Decimal::ONE_HUNDRED)
+        );
+        assert_eq!(max_applications, None);
+    }
+
+    #[test]
+    fn test_validate_reports_every_bad_action() {
+        let mut rule = fixtures::rule("rule-1", Vec::new());
+        rule.actions = vec![
+            DiscountAction::PercentageOff {
+                percent: decimal::to_bson(Decimal::from(150)),
+            },
+            DiscountAction::QuantityTiers {
+                items: ItemSelector::default(),
+                value_type: TierValueType::Percentage,
+                tiers: vec![
+                    QuantityTier {
+                        min_quantity: 5,
+                        value: decimal::to_bson(Decimal::TEN),
+                    },
+                    QuantityTier {
+                        min_quantity: 3,
+                        value: decimal::to_bson(Decimal::TEN),
+                    },
+                ],
+                mode: TierMode::Graduated,
+                currency: Currency::USD,
+            },
+        ];
+        let Err(ServiceError::ValidationFailed { fields }) = rule.validate() else {
+            panic!("expected validation to fail");
+        };
+        let fields: Vec<&str> = fields.iter().map(|f| f.field.as_str()).collect();
+        assert_eq!(fields, vec!["actions[0]", "actions[1]"]);
+    }
+
+    #[test]
+    fn test_bundle_condition_needs_every_component() {
+        let components = vec![BundleComponent::new("a", 1), BundleComponent::new("b", 2)];
+        let bundle = Condition::Bundle {
+            components: components.clone(),
+        };
+        let full = cart(vec![
+            line("a", "a", "20", 2),
+            line("b-1", "b", "10", 2),
+            line("b-2", "b", "10", 3),
+        ]);
+        assert_eq!(bundle_count(&full, &components), 2);
+        assert!(bundle.evaluate(&ctx(full)));
+
+        let short = cart(vec![line("a", "a", "20", 2), line("b", "b", "10", 1)]);
+        assert_eq!(bundle_count(&short, &components), 0);
+        assert!(!bundle.evaluate(&ctx(short)));
+    }
 }
@@ -0,0 +1,135 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{decimal::DecimalError, money::Currency, storage::StorageError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Entity {
+    DiscountRule,
+    Coupon,
+    Membership,
+}
+
+impl fmt::Display for Entity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Entity::DiscountRule => write!(f, "discount rule"),
+            Entity::Coupon => write!(f, "coupon"),
+            Entity::Membership => write!(f, "membership"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Error returned by every RPC. `key` is whatever the caller looked the
+/// entity up by: an id, a code or a customer id.
+#[derive(Debug, Clone, PartialEq, Eq, Error, Serialize, Deserialize)]
+pub enum ServiceError {
+    #[error("{entity} {key} not found")]
+    NotFound { entity: Entity, key: String },
+    #[error("{entity} {key} has expired")]
+    Expired { entity: Entity, key: String },
+    #[error("{entity} {key} is not active yet")]
+    NotYetActive { entity: Entity, key: String },
+    #[error("{entity} {key} has reached its usage limit")]
+    UsageLimitReached { entity: Entity, key: String },
+    #[error("{entity} {key} is not active")]
+    Inactive { entity: Entity, key: String },
+    /// Usable in general, but its restrictions rule out this purchase.
+    #[error("{entity} {key} does not apply: {reason}")]
+    NotApplicable {
+        entity: Entity,
+        key: String,
+        reason: String,
+    },
+    /// The entity's amounts are in `currency` but the cart's are in
+    /// `expected`.
+    #[error("{entity} {key} is priced in {currency}, but the cart is in {expected}")]
+    CurrencyMismatch {
+        entity: Entity,
+        key: String,
+        currency: Currency,
+        expected: Currency,
+    },
+    /// The code cannot be one that was issued, so the shopper most likely
+    /// mistyped it.
+    #[error("{code} does not look like a valid code; check it for typos")]
+    MistypedCode { code: String },
+    #[error("validation failed: {}", describe_fields(fields))]
+    ValidationFailed { fields: Vec<FieldError> },
+    #[error("conflict: {0}")]
+    Conflict(String),
+    #[error("storage error: {0}")]
+    Storage(String),
+}
+
+impl ServiceError {
+    pub fn not_found(entity: Entity, key: impl Into<String>) -> Self {
+        ServiceError::NotFound {
+            entity,
+            key: key.into(),
+        }
+    }
+
+    pub fn not_applicable(entity: Entity, key: impl Into<String>, reason: &str) -> Self {
+        ServiceError::NotApplicable {
+            entity,
+            key: key.into(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn invalid_field(field: impl Into<String>, message: impl Into<String>) -> Self {
+        ServiceError::ValidationFailed {
+            fields: vec![FieldError::new(field, message)],
+        }
+    }
+
+    /// `Ok` when `fields` is empty, otherwise a `ValidationFailed` carrying
+    /// all of them.
+    pub fn check_fields(fields: Vec<FieldError>) -> Result<(), Self> {
+        if fields.is_empty() {
+            Ok(())
+        } else {
+            Err(ServiceError::ValidationFailed { fields })
+        }
+    }
+
+    /// Rejects a money input that is not a usable decimal.
+    pub fn invalid_decimal(field: &str, error: DecimalError) -> Self {
+        Self::invalid_field(field, error.to_string())
+    }
+}
+
+fn describe_fields(fields: &[FieldError]) -> String {
+    fields
+        .iter()
+        .map(|f| format!("{}: {}", f.field, f.message))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl From<StorageError> for ServiceError {
+    fn from(error: StorageError) -> Self {
+        match error {
+            StorageError::Duplicate(message) => ServiceError::Conflict(message),
+            StorageError::Backend(message) => ServiceError::Storage(message),
+        }
+    }
+}
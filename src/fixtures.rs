@@ -0,0 +1,107 @@
+//! Builders shared by the unit tests.
+
+use std::str::FromStr;
+
+use bson::Decimal128;
+use chrono::{DateTime, Utc};
+
+use crate::{
+    cart::{Cart, CartLine},
+    coupon::{Coupon, CouponDiscountType},
+    decimal::Decimal,
+    discount::{DiscountAction, DiscountRule, EvaluationContext},
+    membership::{Membership, MembershipTarget, MembershipTier},
+    money::{Currency, Money},
+};
+
+/// A fixed instant, so tests do not depend on the clock.
+pub fn now() -> DateTime<Utc> {
+    DateTime::from_timestamp(1_700_000_000, 0).unwrap()
+}
+
+pub fn d128(s: &str) -> Decimal128 {
+    Decimal128::from_str(s).unwrap()
+}
+
+pub fn dec(s: &str) -> Decimal {
+    Decimal::from_str(s).unwrap()
+}
+
+pub fn usd(s: &str) -> Money {
+    Money::new(dec(s), Currency::USD)
+}
+
+pub fn line(id: &str, product_id: &str, unit_price: &str, quantity: i32) -> CartLine {
+    CartLine::new(id, product_id, d128(unit_price), quantity)
+}
+
+pub fn cart(lines: Vec<CartLine>) -> Cart {
+    Cart::new("shop-1", lines, d128("5"))
+}
+
+pub fn ctx(cart: Cart) -> EvaluationContext {
+    EvaluationContext::new(cart, now())
+}
+
+/// An active, unconditional rule in `shop-1`.
+pub fn rule(id: &str, actions: Vec<DiscountAction>) -> DiscountRule {
+    DiscountRule {
+        id: id.to_string(),
+        shop_id: "shop-1".to_string(),
+        name: id.to_string(),
+        code: None,
+        recurrence: None,
+        conditions: Vec::new(),
+        actions,
+        priority: 0,
+        start_date: None,
+        end_date: None,
+        is_active: true,
+        usage_count: 0,
+        max_usage: None,
+        max_usage_per_customer: None,
+        is_exclusive: false,
+        stacking_group: None,
+        created_at: now(),
+        updated_at: now(),
+    }
+}
+
+/// An active, unlimited coupon taking `percent` off.
+pub fn coupon(id: &str, shop_id: &str, code: &str, percent: &str) -> Coupon {
+    Coupon {
+        id: id.to_string(),
+        shop_id: shop_id.to_string(),
+        code: code.to_string(),
+        campaign_id: None,
+        description: None,
+        is_active: true,
+        discount_type: CouponDiscountType::Percentage,
+        discount_value: usd(percent),
+        is_single_use: false,
+        used_count: 0,
+        max_uses: None,
+        max_uses_per_customer: None,
+        starts_at: None,
+        expires_at: None,
+        restrictions: Default::default(),
+        created_at: now(),
+        updated_at: now(),
+    }
+}
+
+/// An active, undated Gold membership taking `percent` off.
+pub fn membership(id: &str, shop_id: &str, customer_id: &str, percent: &str) -> Membership {
+    Membership {
+        id: id.to_string(),
+        shop_id: shop_id.to_string(),
+        customer_id: customer_id.to_string(),
+        tier: MembershipTier::new("Gold", MembershipTarget::Customer),
+        discount_percentage: d128(percent),
+        is_active: true,
+        starts_at: None,
+        expires_at: None,
+        created_at: now(),
+        updated_at: now(),
+    }
+}
@@ -1,7 +1,21 @@
+pub mod cart;
 pub mod coupon;
+pub mod coupon_code;
 pub mod datetime;
+pub mod decimal;
 pub mod discount;
+pub mod error;
+#[cfg(test)]
+mod fixtures;
 pub mod membership;
+pub mod money;
+pub mod pricing;
+pub mod redemption;
+pub mod schedule;
+pub mod service;
+pub mod stacking;
+pub mod storage;
+pub mod trace;
 
 // #[cfg(test)]
 // mod tests {
@@ -1,4 +1,9 @@
-use crate::datetime::datetime_serialization;
+use crate::{
+    datetime::datetime_serialization,
+    decimal::{self, Decimal},
+    error::{FieldError, ServiceError},
+    money::Money,
+};
 use chrono::{DateTime, Utc};
 use mongodb::bson::Decimal128;
 use serde::{Deserialize, Serialize};
@@ -48,10 +53,10 @@ pub struct Membership {
     pub tier: MembershipTier,
     pub discount_percentage: Decimal128,
     pub is_active: bool,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     #[serde(with = "datetime_serialization")]
     pub starts_at: Option<DateTime<Utc>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     #[serde(with = "datetime_serialization")]
     pub expires_at: Option<DateTime<Utc>>,
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
@@ -61,6 +66,37 @@ pub struct Membership {
 }
 
 impl Membership {
+    /// Checks the fields a stored membership must have before it can be
+    /// saved.
+    pub fn validate(&self) -> Result<(), ServiceError> {
+        let mut fields = Vec::new();
+        if self.shop_id.trim().is_empty() {
+            fields.push(FieldError::new("shop_id", "must not be empty"));
+        }
+        if self.customer_id.trim().is_empty() {
+            fields.push(FieldError::new("customer_id", "must not be empty"));
+        }
+        if self.tier.name.trim().is_empty() {
+            fields.push(FieldError::new("tier.name", "must not be empty"));
+        }
+        match decimal::from_bson(self.discount_percentage) {
+            Ok(p) if p >= Decimal::ZERO && p <= Decimal::ONE_HUNDRED => {}
+            _ => fields.push(FieldError::new(
+                "discount_percentage",
+                "must be between 0 and 100",
+            )),
+        }
+        if let (Some(start), Some(end)) = (self.starts_at, self.expires_at)
+            && start > end
+        {
+            fields.push(FieldError::new(
+                "expires_at",
+                "must not be before starts_at",
+            ));
+        }
+        ServiceError::check_fields(fields)
+    }
+
     pub fn is_valid_at(&self, date: DateTime<Utc>) -> bool {
         if !self.is_active {
             return false;
@@ -77,32 +113,107 @@ impl Membership {
     pub fn get_discount_value(&self) -> Decimal128 {
         self.discount_percentage
     }
+
+    /// The total left after taking the membership percentage off `total`,
+    /// rounded to whole minor units of its currency.
+    pub fn apply_to_total(&self, total: &Money) -> Money {
+        let currency = total.currency;
+        let total = total.value().max(Decimal::ZERO);
+        let percent = decimal::from_bson_or_zero(self.discount_percentage)
+            .clamp(Decimal::ZERO, Decimal::ONE_HUNDRED);
+        Money::new(
+            total - currency.round(decimal::percent_of(total, percent)),
+            currency,
+        )
+    }
 }
 
 #[tarpc::service]
 pub trait MembershipService {
-    async fn create_membership(membership: Membership) -> Result<Membership, String>;
-    async fn get_membership(id: String) -> Result<Membership, String>;
-    async fn update_membership(membership: Membership) -> Result<Membership, String>;
-    async fn delete_membership(id: String) -> Result<(), String>;
-    async fn list_memberships(shop_id: String) -> Result<Vec<Membership>, String>;
+    async fn create_membership(membership: Membership) -> Result<Membership, ServiceError>;
+    async fn get_membership(id: String) -> Result<Membership, ServiceError>;
+    async fn update_membership(membership: Membership) -> Result<Membership, ServiceError>;
+    async fn delete_membership(id: String) -> Result<(), ServiceError>;
+    async fn list_memberships(shop_id: String) -> Result<Vec<Membership>, ServiceError>;
     async fn apply_membership_discount(
         membership_id: String,
-        cart_total: Decimal128,
-    ) -> Result<Decimal128, String>;
-    async fn validate_membership(membership_id: String) -> Result<bool, String>;
-    async fn get_membership_by_customer_id(customer_id: String) -> Result<Membership, String>;
+        cart_total: Money,
+    ) -> Result<Money, ServiceError>;
+    async fn validate_membership(membership_id: String) -> Result<bool, ServiceError>;
+    async fn get_membership_by_customer_id(customer_id: String)
+    -> Result<Membership, ServiceError>;
     async fn get_membership_by_customer_id_and_shop(
         customer_id: String,
         shop_id: String,
-    ) -> Result<Membership, String>;
-    async fn get_membership_by_tier(tier: MembershipTier) -> Result<Membership, String>;
+    ) -> Result<Membership, ServiceError>;
+    async fn get_membership_by_tier(tier: MembershipTier) -> Result<Membership, ServiceError>;
     async fn get_membership_by_tier_and_shop(
         tier: MembershipTier,
         shop_id: String,
-    ) -> Result<Membership, String>;
+    ) -> Result<Membership, ServiceError>;
     async fn get_membership_by_tier_and_customer(
         tier: MembershipTier,
         customer_id: String,
-    ) -> Result<Membership, String>;
+    ) -> Result<Membership, ServiceError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures, money::Currency};
+
+    fn membership() -> Membership {
+        fixtures::membership("membership-1", "shop-1", "customer-1", "10")
+    }
+
+    fn round_trip(membership: &Membership) -> Membership {
+        bson::from_document(bson::to_document(membership).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_undated_membership_round_trips_through_bson() {
+        let stored = round_trip(&membership());
+        assert_eq!(stored.starts_at, None);
+        assert_eq!(stored.expires_at, None);
+    }
+
+    #[test]
+    fn test_dated_membership_round_trips_through_bson() {
+        let mut membership = membership();
+        membership.starts_at = Some(membership.created_at);
+        membership.expires_at = Some(membership.created_at + chrono::Duration::days(365));
+        let stored = round_trip(&membership);
+        assert_eq!(stored.starts_at, membership.starts_at);
+        assert_eq!(stored.expires_at, membership.expires_at);
+    }
+
+    #[test]
+    fn test_discount_is_rounded_to_the_totals_currency() {
+        let mut membership = membership();
+        membership.discount_percentage = decimal::to_bson(Decimal::new(125, 1));
+        let yen = membership.apply_to_total(&Money::new(Decimal::from(1_005), Currency::JPY));
+        assert_eq!(yen.currency, Currency::JPY);
+        assert_eq!(yen.value(), Decimal::from(879));
+        let dollars = membership.apply_to_total(&Money::new(Decimal::new(1_005, 2), Currency::USD));
+        assert_eq!(dollars.value(), Decimal::new(879, 2));
+        let refund = membership.apply_to_total(&Money::new(Decimal::from(-5), Currency::USD));
+        assert_eq!(refund.value(), Decimal::ZERO);
+        membership.discount_percentage = decimal::to_bson(Decimal::from(150));
+        let free = membership.apply_to_total(&Money::new(Decimal::from(20), Currency::USD));
+        assert_eq!(free.value(), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_validity_follows_the_membership_dates() {
+        let now = fixtures::now();
+        let mut membership = membership();
+        assert!(membership.is_valid_at(now));
+        membership.starts_at = Some(now);
+        membership.expires_at = Some(now + chrono::Duration::days(365));
+        assert!(membership.is_valid_at(now));
+        assert!(!membership.is_valid_at(now - chrono::Duration::seconds(1)));
+        assert!(!membership.is_valid_at(now + chrono::Duration::days(366)));
+        membership.is_active = false;
+        assert!(!membership.is_valid_at(now));
+    }
 }
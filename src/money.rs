@@ -0,0 +1,184 @@
+use std::fmt;
+
+use bson::Decimal128;
+use rust_decimal::RoundingStrategy;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::decimal::{self, Decimal};
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{0} is not a three-letter ISO 4217 currency code")]
+pub struct CurrencyError(String);
+
+/// An ISO 4217 currency, held as its upper-case three-letter code.
+///
+/// Carts and amounts stored before currencies were tracked are read as US
+/// dollars, which matches the whole-cent rounding they were priced with.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub const USD: Currency = Currency(*b"USD");
+    pub const JPY: Currency = Currency(*b"JPY");
+    pub const KHR: Currency = Currency(*b"KHR");
+
+    /// Parses a code such as `"usd"` or `"JPY"`.
+    pub fn new(code: &str) -> Result<Self, CurrencyError> {
+        let code = code.trim().to_ascii_uppercase();
+        match code.as_bytes() {
+            &[a, b, c] if code.bytes().all(|b| b.is_ascii_uppercase()) => Ok(Self([a, b, c])),
+            _ => Err(CurrencyError(code)),
+        }
+    }
+
+    pub fn code(&self) -> &str {
+        std::str::from_utf8(&self.0).expect("currency codes are ASCII")
+    }
+
+    /// Digits after the decimal point in amounts of this currency, per
+    /// ISO 4217. Codes missing from the table are taken to have two.
+    pub fn minor_units(&self) -> u32 {
+        match self.code() {
+            "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF"
+            | "UGX" | "UYI" | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+            "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+            "CLF" | "UYW" => 4,
+            _ => 2,
+        }
+    }
+
+    /// The smallest amount of this currency, such as 0.01 for US dollars.
+    pub fn minor_unit(&self) -> Decimal {
+        Decimal::new(1, self.minor_units())
+    }
+
+    /// Rounds to whole minor units, halves away from zero.
+    pub fn round(&self, amount: Decimal) -> Decimal {
+        amount.round_dp_with_strategy(self.minor_units(), RoundingStrategy::MidpointAwayFromZero)
+    }
+
+    /// Cuts to whole minor units, towards zero.
+    pub fn truncate(&self, amount: Decimal) -> Decimal {
+        amount.round_dp_with_strategy(self.minor_units(), RoundingStrategy::ToZero)
+    }
+}
+
+impl Default for Currency {
+    fn default() -> Self {
+        Self::USD
+    }
+}
+
+impl fmt::Debug for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Currency").field(&self.code()).finish()
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl TryFrom<String> for Currency {
+    type Error = CurrencyError;
+
+    fn try_from(code: String) -> Result<Self, Self::Error> {
+        Self::new(&code)
+    }
+}
+
+impl From<Currency> for String {
+    fn from(currency: Currency) -> Self {
+        currency.code().to_string()
+    }
+}
+
+/// An amount in a currency. A bare amount, as stored before currencies were
+/// tracked, is read as US dollars.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "MoneyRepr")]
+pub struct Money {
+    pub amount: Decimal128,
+    pub currency: Currency,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MoneyRepr {
+    Amount(Decimal128),
+    Money {
+        amount: Decimal128,
+        #[serde(default)]
+        currency: Currency,
+    },
+}
+
+impl From<MoneyRepr> for Money {
+    fn from(repr: MoneyRepr) -> Self {
+        match repr {
+            MoneyRepr::Amount(amount) => Self {
+                amount,
+                currency: Currency::default(),
+            },
+            MoneyRepr::Money { amount, currency } => Self { amount, currency },
+        }
+    }
+}
+
+impl Money {
+    pub fn new(amount: Decimal, currency: Currency) -> Self {
+        Self {
+            amount: decimal::to_bson(amount),
+            currency,
+        }
+    }
+
+    /// The amount in exact decimal form; unusable amounts count as zero.
+    pub fn value(&self) -> Decimal {
+        decimal::from_bson_or_zero(self.amount)
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.amount, self.currency)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{d128, dec};
+
+    #[test]
+    fn test_currency_codes_are_three_letters() {
+        assert_eq!(Currency::new(" usd "), Ok(Currency::USD));
+        assert!(Currency::new("US").is_err());
+        assert!(Currency::new("U5D").is_err());
+        assert_eq!(format!("{:?}", Currency::JPY), "Currency(\"JPY\")");
+    }
+
+    #[test]
+    fn test_rounding_follows_minor_units() {
+        assert_eq!(Currency::USD.round(dec("2.345")), dec("2.35"));
+        assert_eq!(Currency::JPY.round(dec("2.5")), dec("3"));
+        let dinar = Currency::new("KWD").unwrap();
+        assert_eq!(dinar.round(dec("1.2345")), dec("1.235"));
+        assert_eq!(Currency::USD.truncate(dec("2.349")), dec("2.34"));
+        assert_eq!(Currency::KHR.minor_unit(), dec("0.01"));
+    }
+
+    #[test]
+    fn test_bare_amounts_read_as_us_dollars() {
+        let bare: Money = bson::from_bson(bson::Bson::Decimal128(d128("9.99"))).unwrap();
+        assert_eq!(bare, Money::new(dec("9.99"), Currency::USD));
+
+        let yen = Money::new(dec("500"), Currency::JPY);
+        let stored: Money = bson::from_bson(bson::to_bson(&yen).unwrap()).unwrap();
+        assert_eq!(stored, yen);
+    }
+}
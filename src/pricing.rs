@@ -0,0 +1,889 @@
+mod bogo;
+mod bundle;
+mod tiers;
+
+use std::cmp::Reverse;
+
+use bson::Decimal128;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cart::CartLine,
+    coupon::{Coupon, CouponDiscountType, CouponRejection},
+    decimal::{self, Decimal},
+    discount::{DiscountAction, DiscountRule, EvaluationContext},
+    money::{Currency, Money},
+    stacking::{self, StackingPolicy, SuppressedRule, SuppressionReason},
+};
+
+/// A single discount action that took effect, with the amount it removed
+/// from the cart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppliedAction {
+    pub rule_id: String,
+    pub action: DiscountAction,
+    pub amount: Decimal128,
+    /// How `amount` splits over the cart lines; the shares add up to it.
+    /// Cart-wide actions are spread in proportion to what was left of each
+    /// line when they applied.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allocations: Vec<LineAllocation>,
+}
+
+/// The part of a discount that falls on one cart line, for partial refunds
+/// and per-line tax. `quantity` is the number of units the discount covers.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LineAllocation {
+    pub line_id: String,
+    pub quantity: i32,
+    pub amount: Decimal128,
+}
+
+/// A coupon that took effect and what it saved.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppliedCoupon {
+    pub coupon_id: String,
+    pub code: String,
+    pub discount_type: CouponDiscountType,
+    pub amount: Decimal128,
+    pub free_shipping: bool,
+    /// How `amount` splits over the coupon's eligible lines.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allocations: Vec<LineAllocation>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PricingResult {
+    pub original_total: Decimal128,
+    pub applied_actions: Vec<AppliedAction>,
+    pub discount_total: Decimal128,
+    pub final_total: Decimal128,
+    pub free_shipping: bool,
+    pub shipping_total: Decimal128,
+    pub applied_rule_ids: Vec<String>,
+    pub suppressed_rules: Vec<SuppressedRule>,
+    /// The context's coupon, when it could be used. It is applied after the
+    /// rules, to what they left, and counts against the combined cap.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coupon: Option<AppliedCoupon>,
+    /// Why the context's coupon could not be used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coupon_rejection: Option<CouponRejection>,
+}
+
+/// Prices the cart described by `ctx` against `rules` with the default
+/// stacking policy.
+pub fn price(ctx: &EvaluationContext, rules: &[DiscountRule]) -> PricingResult {
+    price_with_policy(ctx, rules, &StackingPolicy::default())
+}
+
+/// Prices the cart described by `ctx` against the rules `policy` selects.
+///
+/// Selected rules are applied highest priority first. Actions run against
+/// the running total, so a percentage applied after a fixed amount is taken
+/// from what is left, and the total never drops below zero or below the
+/// policy's combined cap.
+pub fn price_with_policy(
+    ctx: &EvaluationContext,
+    rules: &[DiscountRule],
+    policy: &StackingPolicy,
+) -> PricingResult {
+    let (selected, mut suppressed_rules) = stacking::select(ctx, rules, policy);
+    let mut state = PricingState::new(ctx, policy);
+    let mut applied_rule_ids = Vec::new();
+
+    for rule in selected {
+        let waives_shipping = rule
+            .actions
+            .iter()
+            .any(|a| matches!(a, DiscountAction::FreeShipping));
+        if state.cap_left == Some(Decimal::ZERO) && !waives_shipping {
+            suppressed_rules.push(SuppressedRule {
+                rule_id: rule.id.clone(),
+                reason: SuppressionReason::CombinedCapReached,
+            });
+            continue;
+        }
+        state.apply_rule(ctx, rule);
+        applied_rule_ids.push(rule.id.clone());
+    }
+
+    let (coupon, coupon_rejection) = match &ctx.applied_coupon {
+        Some(coupon) => match coupon.check(ctx) {
+            Ok(()) => (Some(state.apply_coupon(ctx, coupon)), None),
+            Err(rejection) => (None, Some(rejection)),
+        },
+        None => (None, None),
+    };
+
+    PricingResult {
+        original_total: decimal::to_bson(state.original),
+        discount_total: decimal::to_bson(state.original - state.remaining),
+        final_total: decimal::to_bson(state.remaining),
+        free_shipping: state.free_shipping,
+        shipping_total: if state.free_shipping {
+            decimal::to_bson(Decimal::ZERO)
+        } else {
+            ctx.cart.shipping_cost
+        },
+        applied_actions: state.applied_actions,
+        applied_rule_ids,
+        suppressed_rules,
+        coupon,
+        coupon_rejection,
+    }
+}
+
+/// Applies `coupon` on its own to the cart in `ctx`, or says why it cannot
+/// be used there. Rules are not considered; see [`price_with_policy`] for
+/// a coupon combined with them.
+pub fn apply_coupon(
+    ctx: &EvaluationContext,
+    coupon: &Coupon,
+) -> Result<AppliedCoupon, CouponRejection> {
+    coupon.check(ctx)?;
+    let mut state = PricingState::new(ctx, &StackingPolicy::default());
+    Ok(state.apply_coupon(ctx, coupon))
+}
+
+/// Applies the cart-wide actions of `rule` (percentage and fixed amount off)
+/// to a bare `total`, for callers that have no cart to evaluate. Conditions
+/// are not checked and line-level actions are skipped; callers check the
+/// currency with [`DiscountRule::check_currency`] first.
+pub fn apply_to_total(rule: &DiscountRule, total: &Money) -> Money {
+    let currency = total.currency;
+    let mut remaining = total.value().max(Decimal::ZERO);
+    for action in &rule.actions {
+        let amount = match action {
+            DiscountAction::PercentageOff { percent } => {
+                let percent =
+                    decimal::from_bson_or_zero(*percent).clamp(Decimal::ZERO, Decimal::ONE_HUNDRED);
+                currency.round(decimal::percent_of(remaining, percent))
+            }
+            DiscountAction::FixedAmountOff { amount } => {
+                currency.round(amount.value().max(Decimal::ZERO))
+            }
+            _ => Decimal::ZERO,
+        };
+        remaining -= amount.min(remaining);
+    }
+    Money::new(remaining, currency)
+}
+
+/// What `rule` would save the customer if it were the only rule applied,
+/// counting a shipping waiver at the cart's shipping cost.
+pub(crate) fn standalone_saving(
+    ctx: &EvaluationContext,
+    rule: &DiscountRule,
+    policy: &StackingPolicy,
+) -> Decimal {
+    let mut state = PricingState::new(ctx, policy);
+    state.apply_rule(ctx, rule);
+    let shipping = if state.free_shipping {
+        decimal::from_bson_or_zero(ctx.cart.shipping_cost).max(Decimal::ZERO)
+    } else {
+        Decimal::ZERO
+    };
+    state.original - state.remaining + shipping
+}
+
+struct PricingState {
+    currency: Currency,
+    /// The cart total and line subtotals start rounded to the cart currency,
+    /// so every amount taken off and allocated is in whole minor units.
+    original: Decimal,
+    remaining: Decimal,
+    /// What is left of each cart line, in cart order.
+    line_remaining: Vec<Decimal>,
+    cap_left: Option<Decimal>,
+    free_shipping: bool,
+    applied_actions: Vec<AppliedAction>,
+}
+
+impl PricingState {
+    fn new(ctx: &EvaluationContext, policy: &StackingPolicy) -> Self {
+        let currency = ctx.cart.currency;
+        let original = currency.round(ctx.cart_total().value()).max(Decimal::ZERO);
+        let cap_left = policy.max_combined_percentage.map(|percent| {
+            let percent =
+                decimal::from_bson_or_zero(percent).clamp(Decimal::ZERO, Decimal::ONE_HUNDRED);
+            currency.round(decimal::percent_of(original, percent))
+        });
+        Self {
+            currency,
+            original,
+            remaining: original,
+            line_remaining: ctx
+                .cart
+                .lines
+                .iter()
+                .map(|line| currency.round(line.subtotal()).max(Decimal::ZERO))
+                .collect(),
+            cap_left,
+            free_shipping: false,
+            applied_actions: Vec::new(),
+        }
+    }
+
+    fn apply_rule(&mut self, ctx: &EvaluationContext, rule: &DiscountRule) {
+        for action in &rule.actions {
+            // Set by actions that price individual lines; the others are
+            // spread afterwards over the lines `spread_items` selects, or
+            // over the whole cart.
+            let mut line_allocations = None;
+            let mut spread_items = None;
+            let amount = match action {
+                DiscountAction::PercentageOff { percent } => {
+                    let percent = decimal::from_bson_or_zero(*percent)
+                        .clamp(Decimal::ZERO, Decimal::ONE_HUNDRED);
+                    self.currency
+                        .round(decimal::percent_of(self.remaining, percent))
+                }
+                DiscountAction::FixedAmountOff { amount } => {
+                    self.currency.round(amount.value().max(Decimal::ZERO))
+                }
+                DiscountAction::FreeShipping => {
+                    self.free_shipping = true;
+                    Decimal::ZERO
+                }
+                DiscountAction::BuyXGetY {
+                    buy,
+                    buy_quantity,
+                    get,
+                    get_quantity,
+                    get_percent_off,
+                    max_applications,
+                } => {
+                    let percent = decimal::from_bson_or_zero(*get_percent_off)
+                        .clamp(Decimal::ZERO, Decimal::ONE_HUNDRED);
+                    self.fit_to_lines(
+                        ctx,
+                        &mut line_allocations,
+                        bogo::allocate(
+                            &ctx.cart,
+                            buy,
+                            *buy_quantity,
+                            get,
+                            *get_quantity,
+                            percent,
+                            *max_applications,
+                        ),
+                    )
+                }
+                DiscountAction::QuantityTiers {
+                    items,
+                    value_type,
+                    tiers,
+                    mode,
+                    ..
+                } => self.fit_to_lines(
+                    ctx,
+                    &mut line_allocations,
+                    tiers::allocate_quantity(&ctx.cart, items, *value_type, tiers, *mode),
+                ),
+                DiscountAction::BundlePrice {
+                    components,
+                    price,
+                    max_applications,
+                } => {
+                    let price = self.currency.round(price.value().max(Decimal::ZERO));
+                    self.fit_to_lines(
+                        ctx,
+                        &mut line_allocations,
+                        bundle::allocate(&ctx.cart, components, price, *max_applications),
+                    )
+                }
+                DiscountAction::SpendTiers {
+                    items,
+                    value_type,
+                    tiers,
+                    mode,
+                    ..
+                } => {
+                    spread_items = Some(items);
+                    tiers::spend_discount(&ctx.cart, items, *value_type, tiers, *mode)
+                        .min(self.left_on(ctx, |line| items.matches(line)))
+                }
+            };
+            let amount = self.take(amount);
+            let allocations = match line_allocations {
+                Some(mut allocations) => {
+                    trim_allocations(&mut allocations, amount);
+                    allocations
+                }
+                None => self.spread(ctx, amount, |line| {
+                    spread_items.is_none_or(|items| items.matches(line))
+                }),
+            };
+            self.take_from_lines(ctx, &allocations);
+            self.applied_actions.push(AppliedAction {
+                rule_id: rule.id.clone(),
+                action: action.clone(),
+                amount: decimal::to_bson(amount),
+                allocations,
+            });
+        }
+    }
+
+    /// Takes the coupon's discount off what is left of its eligible lines.
+    fn apply_coupon(&mut self, ctx: &EvaluationContext, coupon: &Coupon) -> AppliedCoupon {
+        let eligible = |line: &CartLine| coupon.restrictions.is_line_eligible(line);
+        let amount = self.take(coupon.discount_on(self.left_on(ctx, eligible)));
+        let allocations = self.spread(ctx, amount, eligible);
+        self.take_from_lines(ctx, &allocations);
+        let free_shipping = matches!(coupon.discount_type, CouponDiscountType::FreeShipping);
+        self.free_shipping |= free_shipping;
+        AppliedCoupon {
+            coupon_id: coupon.id.clone(),
+            code: coupon.code.clone(),
+            discount_type: coupon.discount_type.clone(),
+            amount: decimal::to_bson(amount),
+            free_shipping,
+            allocations,
+        }
+    }
+
+    /// Limits `amount` to the remaining total and the combined cap, and
+    /// takes what is left of it off both.
+    fn take(&mut self, amount: Decimal) -> Decimal {
+        let mut amount = amount.min(self.remaining);
+        if let Some(cap_left) = &mut self.cap_left {
+            amount = amount.min(*cap_left);
+            *cap_left -= amount;
+        }
+        self.remaining -= amount;
+        amount
+    }
+
+    /// Cuts each of a line-level action's `allocations` down to what is left
+    /// of its line, stores them in `slot` and returns their sum.
+    fn fit_to_lines(
+        &self,
+        ctx: &EvaluationContext,
+        slot: &mut Option<Vec<LineAllocation>>,
+        mut allocations: Vec<LineAllocation>,
+    ) -> Decimal {
+        for allocation in &mut allocations {
+            let left = self
+                .line_index(ctx, &allocation.line_id)
+                .map_or(Decimal::ZERO, |i| self.line_remaining[i]);
+            allocation.amount =
+                decimal::to_bson(decimal::from_bson_or_zero(allocation.amount).min(left));
+        }
+        allocations.retain(|a| !decimal::from_bson_or_zero(a.amount).is_zero());
+        let total = allocations
+            .iter()
+            .map(|a| decimal::from_bson_or_zero(a.amount))
+            .sum();
+        *slot = Some(allocations);
+        total
+    }
+
+    /// What is left of the lines `include` accepts.
+    fn left_on(&self, ctx: &EvaluationContext, include: impl Fn(&CartLine) -> bool) -> Decimal {
+        ctx.cart
+            .lines
+            .iter()
+            .zip(&self.line_remaining)
+            .filter(|(line, _)| include(line))
+            .map(|(_, left)| *left)
+            .sum()
+    }
+
+    /// Spreads `amount` over the lines `include` accepts, in proportion to
+    /// what is left of each.
+    fn spread(
+        &self,
+        ctx: &EvaluationContext,
+        amount: Decimal,
+        include: impl Fn(&CartLine) -> bool,
+    ) -> Vec<LineAllocation> {
+        let weights: Vec<Decimal> = ctx
+            .cart
+            .lines
+            .iter()
+            .zip(&self.line_remaining)
+            .map(|(line, left)| if include(line) { *left } else { Decimal::ZERO })
+            .collect();
+        ctx.cart
+            .lines
+            .iter()
+            .zip(split_proportionally(amount, &weights, self.currency))
+            .filter(|(_, share)| !share.is_zero())
+            .map(|(line, share)| LineAllocation {
+                line_id: line.line_id.clone(),
+                quantity: line.quantity,
+                amount: decimal::to_bson(share),
+            })
+            .collect()
+    }
+
+    fn take_from_lines(&mut self, ctx: &EvaluationContext, allocations: &[LineAllocation]) {
+        for allocation in allocations {
+            if let Some(i) = self.line_index(ctx, &allocation.line_id) {
+                self.line_remaining[i] -= decimal::from_bson_or_zero(allocation.amount);
+            }
+        }
+    }
+
+    fn line_index(&self, ctx: &EvaluationContext, line_id: &str) -> Option<usize> {
+        ctx.cart
+            .lines
+            .iter()
+            .position(|line| line.line_id == line_id)
+    }
+}
+
+/// Cuts `allocations` down to `amount` when the remaining total or the
+/// combined cap left less than they add up to, taking the cut from the last
+/// lines first.
+fn trim_allocations(allocations: &mut Vec<LineAllocation>, amount: Decimal) {
+    let mut left = amount;
+    for allocation in allocations.iter_mut() {
+        let share = decimal::from_bson_or_zero(allocation.amount).min(left);
+        allocation.amount = decimal::to_bson(share);
+        left -= share;
+    }
+    allocations.retain(|a| !decimal::from_bson_or_zero(a.amount).is_zero());
+}
+
+/// Splits `amount`, in whole minor units of `currency`, over `weights` in
+/// proportion, each share in whole minor units. Shares are cut down to the
+/// minor unit first, then the units left over go to the largest remainders,
+/// so they always add up to `amount`.
+fn split_proportionally(amount: Decimal, weights: &[Decimal], currency: Currency) -> Vec<Decimal> {
+    let total: Decimal = weights.iter().sum();
+    if total <= Decimal::ZERO {
+        return vec![Decimal::ZERO; weights.len()];
+    }
+    let exact: Vec<Decimal> = weights.iter().map(|w| amount * *w / total).collect();
+    let mut shares: Vec<Decimal> = exact.iter().map(|e| currency.truncate(*e)).collect();
+    let unit = currency.minor_unit();
+    let mut left = amount - shares.iter().sum::<Decimal>();
+    let mut order: Vec<usize> = (0..shares.len()).collect();
+    order.sort_by_key(|&i| Reverse(exact[i] - shares[i]));
+    for i in order {
+        if left < unit {
+            break;
+        }
+        shares[i] += unit;
+        left -= unit;
+    }
+    shares
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        discount::{
+            BundleComponent, Condition, ItemSelector, Operator, SpendTier, TierMode, TierValueType,
+        },
+        fixtures::{cart, coupon, ctx, d128, dec, line, rule, usd},
+    };
+
+    fn amounts(result: &PricingResult) -> Vec<Decimal> {
+        result
+            .applied_actions
+            .iter()
+            .map(|a| decimal::from_bson_or_zero(a.amount))
+            .collect()
+    }
+
+    fn allocated(action: &AppliedAction) -> Vec<(&str, Decimal)> {
+        action
+            .allocations
+            .iter()
+            .map(|a| (a.line_id.as_str(), decimal::from_bson_or_zero(a.amount)))
+            .collect()
+    }
+
+    fn allocated_sum(allocations: &[LineAllocation]) -> Decimal {
+        allocations
+            .iter()
+            .map(|a| decimal::from_bson_or_zero(a.amount))
+            .sum()
+    }
+
+    fn buy_x_get_y(buy: &str, buy_quantity: i32, get: &str, get_quantity: i32) -> DiscountRule {
+        rule(
+            "bogo",
+            vec![DiscountAction::BuyXGetY {
+                buy: ItemSelector::products(vec![buy.to_string()]),
+                buy_quantity,
+                get: ItemSelector::products(vec![get.to_string()]),
+                get_quantity,
+                get_percent_off: d128("100"),
+                max_applications: None,
+            }],
+        )
+    }
+
+    #[test]
+    fn test_percentage_is_taken_from_what_earlier_rules_left() {
+        let mut fixed = rule(
+            "fixed",
+            vec![DiscountAction::FixedAmountOff { amount: usd("10") }],
+        );
+        fixed.priority = 2;
+        let percent = rule(
+            "percent",
+            vec![DiscountAction::PercentageOff {
+                percent: d128("10"),
+            }],
+        );
+
+        let result = price(
+            &ctx(cart(vec![line("a", "a", "100", 1)])),
+            &[percent, fixed],
+        );
+        assert_eq!(amounts(&result), vec![dec("10"), dec("9")]);
+        assert_eq!(decimal::from_bson_or_zero(result.final_total), dec("81"));
+        assert_eq!(decimal::from_bson_or_zero(result.discount_total), dec("19"));
+    }
+
+    #[test]
+    fn test_amounts_are_exact_to_the_cent() {
+        let rules = [rule(
+            "percent",
+            vec![DiscountAction::PercentageOff {
+                percent: d128("15"),
+            }],
+        )];
+
+        let result = price(&ctx(cart(vec![line("a", "a", "19.99", 1)])), &rules);
+        assert_eq!(amounts(&result), vec![dec("3.00")]);
+        assert_eq!(decimal::from_bson_or_zero(result.final_total), dec("16.99"));
+
+        let result = price(
+            &ctx(cart(vec![
+                line("a", "a", "0.1", 1),
+                line("b", "b", "0.2", 1),
+            ])),
+            &rules,
+        );
+        assert_eq!(amounts(&result), vec![dec("0.05")]);
+        assert_eq!(decimal::from_bson_or_zero(result.final_total), dec("0.25"));
+    }
+
+    #[test]
+    fn test_cart_wide_allocations_add_up_to_the_penny() {
+        let ctx = ctx(cart(vec![
+            line("a", "a", "10", 1),
+            line("b", "b", "10", 1),
+            line("c", "c", "10", 1),
+        ]));
+        let rules = [
+            rule(
+                "fixed",
+                vec![DiscountAction::FixedAmountOff { amount: usd("10") }],
+            ),
+            rule(
+                "percent",
+                vec![DiscountAction::PercentageOff {
+                    percent: d128("33.3"),
+                }],
+            ),
+        ];
+
+        let result = price(&ctx, &rules);
+        for action in &result.applied_actions {
+            assert_eq!(
+                allocated_sum(&action.allocations),
+                decimal::from_bson_or_zero(action.amount)
+            );
+            assert!(
+                action
+                    .allocations
+                    .iter()
+                    .all(|a| decimal::from_bson_or_zero(a.amount).scale() <= 2)
+            );
+        }
+        assert_eq!(
+            allocated(&result.applied_actions[0]),
+            vec![("a", dec("3.34")), ("b", dec("3.33")), ("c", dec("3.33"))]
+        );
+        assert_eq!(decimal::from_bson_or_zero(result.final_total), dec("13.34"));
+    }
+
+    #[test]
+    fn test_fractional_line_subtotals_are_allocated_in_whole_cents() {
+        let mut ctx = ctx(cart(vec![
+            line("a", "a", "1.005", 1),
+            line("b", "b", "1.005", 1),
+            line("c", "c", "1.005", 1),
+        ]));
+        ctx.applied_coupon = Some(coupon("coupon", "shop-1", "FREE", "100"));
+
+        let result = price(&ctx, &[]);
+        assert_eq!(
+            decimal::from_bson_or_zero(result.original_total),
+            dec("3.02")
+        );
+        let applied = result.coupon.unwrap();
+        assert_eq!(decimal::from_bson_or_zero(applied.amount), dec("3.02"));
+        assert_eq!(allocated_sum(&applied.allocations), dec("3.02"));
+        assert!(
+            applied
+                .allocations
+                .iter()
+                .all(|a| decimal::from_bson_or_zero(a.amount) <= dec("1.01"))
+        );
+        assert_eq!(decimal::from_bson_or_zero(result.final_total), dec("0"));
+    }
+
+    #[test]
+    fn test_spend_tiers_are_spread_over_the_lines_they_select() {
+        let ctx = ctx(cart(vec![
+            line("a", "a", "100", 1),
+            line("b", "b", "50", 1),
+        ]));
+        let tiers = rule(
+            "tiers",
+            vec![DiscountAction::SpendTiers {
+                items: ItemSelector::products(vec!["a".to_string()]),
+                value_type: TierValueType::Percentage,
+                tiers: vec![SpendTier {
+                    min_spend: usd("50"),
+                    value: d128("10"),
+                }],
+                mode: TierMode::WholeAtReachedTier,
+                currency: Currency::USD,
+            }],
+        );
+
+        let result = price(&ctx, &[tiers]);
+        assert_eq!(
+            allocated(&result.applied_actions[0]),
+            vec![("a", dec("10"))]
+        );
+    }
+
+    #[test]
+    fn test_bundle_price_is_rounded_to_the_cart_currency() {
+        let ctx = ctx(cart(vec![line("a", "a", "20", 4)]).with_currency(Currency::JPY));
+        let bundle = rule(
+            "bundle",
+            vec![DiscountAction::BundlePrice {
+                components: vec![BundleComponent::new("a", 2)],
+                price: Money::new(dec("10.4"), Currency::JPY),
+                max_applications: None,
+            }],
+        );
+
+        let result = price(&ctx, &[bundle]);
+        assert_eq!(decimal::from_bson_or_zero(result.discount_total), dec("60"));
+    }
+
+    #[test]
+    fn test_discounts_never_take_the_total_below_zero() {
+        let rules = [
+            rule(
+                "big",
+                vec![DiscountAction::FixedAmountOff { amount: usd("20") }],
+            ),
+            rule(
+                "more",
+                vec![DiscountAction::PercentageOff {
+                    percent: d128("50"),
+                }],
+            ),
+        ];
+
+        let result = price(&ctx(cart(vec![line("a", "a", "15", 1)])), &rules);
+        assert_eq!(amounts(&result), vec![dec("15"), dec("0")]);
+        assert_eq!(decimal::from_bson_or_zero(result.final_total), dec("0"));
+        assert_eq!(decimal::from_bson_or_zero(result.discount_total), dec("15"));
+    }
+
+    #[test]
+    fn test_only_matching_rules_apply() {
+        let mut big_spender = rule(
+            "big-spender",
+            vec![DiscountAction::PercentageOff {
+                percent: d128("20"),
+            }],
+        );
+        big_spender.conditions = vec![Condition::CartTotal {
+            operator: Operator::GreaterThanOrEqual,
+            value: usd("100"),
+        }];
+        let shipping = rule("shipping", vec![DiscountAction::FreeShipping]);
+
+        let result = price(
+            &ctx(cart(vec![line("a", "a", "50", 1)])),
+            &[big_spender, shipping],
+        );
+        assert!(result.free_shipping);
+        assert_eq!(decimal::from_bson_or_zero(result.shipping_total), dec("0"));
+        assert_eq!(result.applied_actions.len(), 1);
+        assert_eq!(result.applied_actions[0].rule_id, "shipping");
+        assert_eq!(decimal::from_bson_or_zero(result.final_total), dec("50"));
+    }
+
+    #[test]
+    fn test_buy_x_get_y_frees_the_cheapest_units() {
+        let ctx = ctx(cart(vec![
+            line("shirt", "shirt", "30", 2),
+            line("socks-red", "socks", "6", 1),
+            line("socks-blue", "socks", "4", 2),
+        ]));
+
+        let result = price(&ctx, &[buy_x_get_y("shirt", 1, "socks", 1)]);
+        assert_eq!(amounts(&result), vec![dec("8")]);
+        assert_eq!(
+            result.applied_actions[0].allocations,
+            vec![LineAllocation {
+                line_id: "socks-blue".to_string(),
+                quantity: 2,
+                amount: d128("8"),
+            }]
+        );
+        assert_eq!(decimal::from_bson(result.shipping_total), Ok(dec("5")));
+    }
+
+    #[test]
+    fn test_buy_x_get_y_on_one_product_needs_both_groups_in_the_cart() {
+        let rules = [buy_x_get_y("mug", 2, "mug", 1)];
+
+        let result = price(&ctx(cart(vec![line("a", "mug", "9", 5)])), &rules);
+        assert_eq!(amounts(&result), vec![dec("9")]);
+        let result = price(&ctx(cart(vec![line("a", "mug", "9", 6)])), &rules);
+        assert_eq!(amounts(&result), vec![dec("18")]);
+    }
+
+    fn capped(percent: &str) -> StackingPolicy {
+        StackingPolicy {
+            max_combined_percentage: Some(d128(percent)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_combined_cap_stops_stacked_rules() {
+        let ctx = ctx(cart(vec![line("a", "a", "100", 1)]));
+        let thirty = |id: &str, priority| {
+            let mut rule = rule(
+                id,
+                vec![DiscountAction::FixedAmountOff { amount: usd("30") }],
+            );
+            rule.priority = priority;
+            rule
+        };
+        let rules = [thirty("first", 3), thirty("second", 2), thirty("third", 1)];
+
+        let result = price_with_policy(&ctx, &rules, &capped("40"));
+        assert_eq!(decimal::from_bson_or_zero(result.discount_total), dec("40"));
+        assert_eq!(result.applied_rule_ids, vec!["first", "second"]);
+        assert_eq!(
+            result.suppressed_rules,
+            vec![SuppressedRule {
+                rule_id: "third".to_string(),
+                reason: SuppressionReason::CombinedCapReached,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_free_shipping_applies_once_the_cap_is_used_up() {
+        let ctx = ctx(cart(vec![line("a", "a", "100", 1)]));
+        let mut percent = rule(
+            "percent",
+            vec![DiscountAction::PercentageOff {
+                percent: d128("20"),
+            }],
+        );
+        percent.priority = 1;
+        let shipping = rule("shipping", vec![DiscountAction::FreeShipping]);
+
+        let result = price_with_policy(&ctx, &[percent, shipping], &capped("10"));
+        assert_eq!(decimal::from_bson_or_zero(result.discount_total), dec("10"));
+        assert!(result.free_shipping);
+        assert_eq!(decimal::from_bson_or_zero(result.shipping_total), dec("0"));
+    }
+
+    #[test]
+    fn test_apply_to_total_takes_cart_wide_actions_in_order() {
+        let rule = rule(
+            "rule",
+            vec![
+                DiscountAction::PercentageOff {
+                    percent: d128("10"),
+                },
+                DiscountAction::FreeShipping,
+                DiscountAction::FixedAmountOff { amount: usd("5") },
+            ],
+        );
+        assert_eq!(apply_to_total(&rule, &usd("100")).value(), dec("85"));
+        assert_eq!(apply_to_total(&rule, &usd("4")).value(), dec("0"));
+        assert_eq!(apply_to_total(&rule, &usd("-10")).value(), dec("0"));
+    }
+
+    #[test]
+    fn test_coupon_applies_to_what_the_rules_left() {
+        let mut ctx = ctx(cart(vec![line("a", "a", "60", 1), line("b", "b", "40", 1)]));
+        let mut coupon = coupon("coupon", "shop-1", "SAVE10", "10");
+        coupon.restrictions.excluded_product_ids = vec!["b".to_string()];
+        ctx.applied_coupon = Some(coupon);
+        let rules = [rule(
+            "fixed",
+            vec![DiscountAction::FixedAmountOff { amount: usd("10") }],
+        )];
+
+        let result = price(&ctx, &rules);
+        let applied = result.coupon.unwrap();
+        assert_eq!(decimal::from_bson_or_zero(applied.amount), dec("5.40"));
+        assert_eq!(allocated_sum(&applied.allocations), dec("5.40"));
+        assert!(applied.allocations.iter().all(|a| a.line_id == "a"));
+        assert_eq!(decimal::from_bson_or_zero(result.final_total), dec("84.60"));
+        assert_eq!(result.coupon_rejection, None);
+    }
+
+    #[test]
+    fn test_unusable_coupon_is_reported_not_applied() {
+        let mut ctx = ctx(cart(vec![line("a", "a", "60", 1)]));
+        let mut coupon = coupon("coupon", "shop-1", "SAVE10", "10");
+        coupon.restrictions.minimum_subtotal = Some(usd("100"));
+        ctx.applied_coupon = Some(coupon);
+
+        let result = price(&ctx, &[]);
+        assert!(result.coupon.is_none());
+        assert_eq!(
+            result.coupon_rejection,
+            Some(CouponRejection::BelowMinimumSubtotal)
+        );
+        assert_eq!(decimal::from_bson_or_zero(result.final_total), dec("60"));
+    }
+
+    #[test]
+    fn test_split_proportionally_hands_out_leftover_units_by_remainder() {
+        let weights = [dec("1"), dec("1"), dec("1")];
+        assert_eq!(
+            split_proportionally(dec("0.02"), &weights, Currency::USD),
+            vec![dec("0.01"), dec("0.01"), dec("0")]
+        );
+        assert_eq!(
+            split_proportionally(dec("1"), &[dec("2"), dec("1")], Currency::USD),
+            vec![dec("0.67"), dec("0.33")]
+        );
+        assert_eq!(
+            split_proportionally(dec("5"), &[dec("0")], Currency::USD),
+            vec![dec("0")]
+        );
+    }
+
+    #[test]
+    fn test_amounts_are_rounded_to_the_cart_currency() {
+        let ctx = ctx(cart(vec![line("a", "a", "999", 1)]).with_currency(Currency::JPY));
+        let percent = rule(
+            "percent",
+            vec![DiscountAction::PercentageOff {
+                percent: d128("15"),
+            }],
+        );
+
+        let result = price(&ctx, &[percent]);
+        assert_eq!(amounts(&result), vec![dec("150")]);
+        assert_eq!(decimal::from_bson_or_zero(result.final_total), dec("849"));
+    }
+}
@@ -0,0 +1,218 @@
+use std::cmp::Reverse;
+
+use crate::{
+    cart::Cart,
+    decimal::{self, Decimal},
+    discount::ItemSelector,
+    pricing::LineAllocation,
+};
+
+/// The units of one cart line that the offer could use.
+struct Group {
+    line: usize,
+    price: Decimal,
+    buy: bool,
+    get: bool,
+    left: i32,
+}
+
+/// Units one application takes from a group: bought, then discounted.
+struct Take {
+    group: usize,
+    bought: i32,
+    got: i32,
+}
+
+/// Prices a buy-X-get-Y offer, returning the discount per cart line.
+///
+/// Applications are formed greedily until the cart or `max_applications`
+/// runs out. Each one takes its `buy_quantity` bought units from the most
+/// expensive units only the buy selector matches, then from units both
+/// selectors match, so overlapping units stay available as discounted ones
+/// where possible. Its `get_quantity` discounted units are then the cheapest
+/// remaining units the get selector matches.
+///
+/// Units are counted per line rather than one by one: an application is
+/// repeated for as long as every line it takes from has units for it, so the
+/// work grows with the number of lines, not with their quantities.
+pub(crate) fn allocate(
+    cart: &Cart,
+    buy: &ItemSelector,
+    buy_quantity: i32,
+    get: &ItemSelector,
+    get_quantity: i32,
+    percent_off: Decimal,
+    max_applications: Option<i32>,
+) -> Vec<LineAllocation> {
+    if buy_quantity <= 0 || get_quantity <= 0 {
+        return Vec::new();
+    }
+
+    let mut groups = Vec::new();
+    for (index, line) in cart.lines.iter().enumerate() {
+        let (is_buy, is_get) = (buy.matches(line), get.matches(line));
+        if line.quantity <= 0 || !(is_buy || is_get) {
+            continue;
+        }
+        groups.push(Group {
+            line: index,
+            price: line.price().max(Decimal::ZERO),
+            buy: is_buy,
+            get: is_get,
+            left: line.quantity,
+        });
+    }
+    // Most expensive first; the sort is stable, so ties keep cart order.
+    groups.sort_by_key(|group| Reverse(group.price));
+
+    let mut discounted = vec![0i32; cart.lines.len()];
+    let mut applications = 0;
+    while max_applications.is_none_or(|max| applications < max) {
+        let Some(takes) = next_application(&groups, buy_quantity, get_quantity) else {
+            break;
+        };
+        let repeats = takes
+            .iter()
+            .map(|take| groups[take.group].left / (take.bought + take.got))
+            .min()
+            .unwrap_or(0)
+            .min(max_applications.map_or(i32::MAX, |max| max - applications));
+        for take in &takes {
+            let group = &mut groups[take.group];
+            group.left -= (take.bought + take.got) * repeats;
+            discounted[group.line] += take.got * repeats;
+        }
+        applications += repeats;
+    }
+
+    cart.lines
+        .iter()
+        .zip(discounted)
+        .filter(|(_, quantity)| *quantity > 0)
+        .map(|(line, quantity)| {
+            let value = line.price().max(Decimal::ZERO) * Decimal::from(quantity);
+            LineAllocation {
+                line_id: line.line_id.clone(),
+                quantity,
+                amount: decimal::to_bson(
+                    cart.currency.round(decimal::percent_of(value, percent_off)),
+                ),
+            }
+        })
+        .collect()
+}
+
+/// What the next application takes from each group, or `None` when the
+/// units left cannot make up another one.
+fn next_application(groups: &[Group], buy_quantity: i32, get_quantity: i32) -> Option<Vec<Take>> {
+    let mut bought = vec![0; groups.len()];
+    let mut need = buy_quantity;
+    for overlapping in [false, true] {
+        for (i, group) in groups.iter().enumerate() {
+            if group.buy && group.get == overlapping {
+                bought[i] = group.left.min(need);
+                need -= bought[i];
+            }
+        }
+    }
+    if need > 0 {
+        return None;
+    }
+
+    let mut got = vec![0; groups.len()];
+    let mut need = get_quantity;
+    for (i, group) in groups.iter().enumerate().rev() {
+        if group.get {
+            got[i] = (group.left - bought[i]).min(need);
+            need -= got[i];
+        }
+    }
+    if need > 0 {
+        return None;
+    }
+
+    Some(
+        bought
+            .into_iter()
+            .zip(got)
+            .enumerate()
+            .filter(|(_, (bought, got))| bought + got > 0)
+            .map(|(group, (bought, got))| Take { group, bought, got })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{cart, dec, line};
+
+    fn products(ids: &[&str]) -> ItemSelector {
+        ItemSelector::products(ids.iter().map(|id| id.to_string()).collect())
+    }
+
+    fn discounted(allocations: &[LineAllocation]) -> Vec<(&str, i32, Decimal)> {
+        allocations
+            .iter()
+            .map(|a| {
+                (
+                    a.line_id.as_str(),
+                    a.quantity,
+                    decimal::from_bson_or_zero(a.amount),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_overlapping_units_are_bought_last() {
+        let cart = cart(vec![line("a", "a", "10", 1), line("b", "b", "5", 2)]);
+        let allocations = allocate(
+            &cart,
+            &products(&["a", "b"]),
+            1,
+            &products(&["b"]),
+            1,
+            dec("100"),
+            None,
+        );
+        assert_eq!(discounted(&allocations), vec![("b", 1, dec("5"))]);
+    }
+
+    #[test]
+    fn test_cheapest_units_are_discounted_across_lines() {
+        let cart = cart(vec![
+            line("a", "a", "30", 2),
+            line("b", "b", "20", 3),
+            line("c", "c", "10", 1),
+        ]);
+        let any = products(&["a", "b", "c"]);
+        let allocations = allocate(&cart, &any, 2, &any, 1, dec("50"), None);
+        assert_eq!(
+            discounted(&allocations),
+            vec![("b", 1, dec("10")), ("c", 1, dec("5"))]
+        );
+    }
+
+    #[test]
+    fn test_max_applications_caps_repeats() {
+        let cart = cart(vec![line("a", "a", "4", 10)]);
+        let a = products(&["a"]);
+        let allocations = allocate(&cart, &a, 1, &a, 1, dec("50"), Some(3));
+        assert_eq!(discounted(&allocations), vec![("a", 3, dec("6"))]);
+    }
+
+    #[test]
+    fn test_large_quantities_are_counted_not_enumerated() {
+        let cart = cart(vec![
+            line("a", "a", "2", 1_000_000),
+            line("b", "b", "1", 1_000_000),
+        ]);
+        let any = products(&["a", "b"]);
+        let allocations = allocate(&cart, &any, 1, &any, 1, dec("50"), None);
+        assert_eq!(
+            discounted(&allocations),
+            vec![("b", 1_000_000, dec("500000"))]
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use std::cmp::Reverse;
+
+use crate::{
+    cart::Cart,
+    decimal::{self, Decimal},
+    discount::{BundleComponent, bundle_count},
+    pricing::{LineAllocation, split_proportionally},
+};
+
+/// Prices a bundle offer, returning the saving per cart line.
+///
+/// Each bundle uses the most expensive units of its products first. The
+/// saving is what those units cost at their regular prices less the bundle
+/// price, and it is spread over their lines in proportion to their value. A
+/// bundle price above the regular price saves nothing.
+pub(crate) fn allocate(
+    cart: &Cart,
+    components: &[BundleComponent],
+    price: Decimal,
+    max_applications: Option<i32>,
+) -> Vec<LineAllocation> {
+    let count = bundle_count(cart, components)
+        .min(max_applications.unwrap_or(i32::MAX))
+        .max(0);
+    if count == 0 {
+        return Vec::new();
+    }
+
+    // Units and their regular value taken from each participating line.
+    let mut used: Vec<(usize, i32, Decimal)> = Vec::new();
+    for component in components {
+        let mut lines: Vec<usize> = (0..cart.lines.len())
+            .filter(|&i| {
+                cart.lines[i].product_id == component.product_id && cart.lines[i].quantity > 0
+            })
+            .collect();
+        lines.sort_by_key(|&i| Reverse(cart.lines[i].price()));
+        let mut needed = component.quantity.saturating_mul(count);
+        for i in lines {
+            if needed == 0 {
+                break;
+            }
+            let units = cart.lines[i].quantity.min(needed);
+            needed -= units;
+            let value = cart.lines[i].price().max(Decimal::ZERO) * Decimal::from(units);
+            used.push((i, units, value));
+        }
+    }
+
+    let regular: Decimal = used.iter().map(|(_, _, value)| value).sum();
+    let saving = cart
+        .currency
+        .round(regular - price * Decimal::from(count))
+        .max(Decimal::ZERO);
+    let weights: Vec<Decimal> = used.iter().map(|(_, _, value)| *value).collect();
+    used.iter()
+        .zip(split_proportionally(saving, &weights, cart.currency))
+        .filter(|(_, share)| !share.is_zero())
+        .map(|((i, units, _), share)| LineAllocation {
+            line_id: cart.lines[*i].line_id.clone(),
+            quantity: *units,
+            amount: decimal::to_bson(share),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{cart, dec, line};
+
+    fn components() -> Vec<BundleComponent> {
+        vec![BundleComponent::new("a", 1), BundleComponent::new("b", 2)]
+    }
+
+    fn saved(allocations: &[LineAllocation]) -> Vec<(&str, i32, Decimal)> {
+        allocations
+            .iter()
+            .map(|a| {
+                (
+                    a.line_id.as_str(),
+                    a.quantity,
+                    decimal::from_bson_or_zero(a.amount),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bundle_saving_is_split_by_regular_value() {
+        let cart = cart(vec![line("a", "a", "20", 1), line("b", "b", "10", 2)]);
+        let allocations = allocate(&cart, &components(), dec("30"), None);
+        assert_eq!(
+            saved(&allocations),
+            vec![("a", 1, dec("5")), ("b", 2, dec("5"))]
+        );
+    }
+
+    #[test]
+    fn test_bundles_use_the_most_expensive_units_first() {
+        let cart = cart(vec![
+            line("cheap", "a", "15", 1),
+            line("dear", "a", "20", 1),
+            line("b", "b", "10", 2),
+        ]);
+        let allocations = allocate(&cart, &components(), dec("30"), None);
+        assert_eq!(
+            saved(&allocations),
+            vec![("dear", 1, dec("5")), ("b", 2, dec("5"))]
+        );
+    }
+
+    #[test]
+    fn test_max_applications_limits_bundles() {
+        let cart = cart(vec![line("a", "a", "20", 3), line("b", "b", "10", 6)]);
+        let allocations = allocate(&cart, &components(), dec("30"), Some(2));
+        assert_eq!(
+            saved(&allocations),
+            vec![("a", 2, dec("10")), ("b", 4, dec("10"))]
+        );
+    }
+
+    #[test]
+    fn test_bundle_price_above_regular_saves_nothing() {
+        let cart = cart(vec![line("a", "a", "20", 1), line("b", "b", "10", 2)]);
+        assert!(allocate(&cart, &components(), dec("45"), None).is_empty());
+        assert!(allocate(&cart, &components()[..1], dec("10"), Some(0)).is_empty());
+    }
+}
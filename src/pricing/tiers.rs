@@ -0,0 +1,313 @@
+use bson::Decimal128;
+
+use crate::{
+    cart::Cart,
+    decimal::{self, Decimal},
+    discount::{ItemSelector, QuantityTier, SpendTier, TierMode, TierValueType},
+    money::Currency,
+    pricing::LineAllocation,
+};
+
+/// Prices a quantity tiered action, returning the discount per cart line.
+///
+/// Units are counted together over every line `items` selects. With
+/// [`TierMode::Graduated`] they are numbered in cart order, and each unit
+/// gets the value of the band its number falls in. Counts saturate at
+/// `i32::MAX` rather than overflowing.
+pub(crate) fn allocate_quantity(
+    cart: &Cart,
+    items: &ItemSelector,
+    value_type: TierValueType,
+    tiers: &[QuantityTier],
+    mode: TierMode,
+) -> Vec<LineAllocation> {
+    let lines: Vec<_> = cart
+        .lines
+        .iter()
+        .filter(|line| line.quantity > 0 && items.matches(line))
+        .collect();
+    let total = lines
+        .iter()
+        .fold(0i32, |total, line| total.saturating_add(line.quantity));
+    let bands = bands(
+        tiers.iter().map(|t| (t.min_quantity, t.value)),
+        value_type,
+        i32::MAX,
+        cart.currency,
+    );
+    let reached = bands.iter().rev().find(|band| band.from <= total);
+
+    let mut counted = 0i32;
+    let mut allocations = Vec::new();
+    for line in lines {
+        let (first, last) = (
+            counted.saturating_add(1),
+            counted.saturating_add(line.quantity),
+        );
+        counted = last;
+        let covered: Vec<(i32, Decimal)> = match mode {
+            TierMode::WholeAtReachedTier => reached
+                .map(|band| vec![(line.quantity, band.value)])
+                .unwrap_or_default(),
+            TierMode::Graduated => bands
+                .iter()
+                .map(|band| {
+                    let units = last.min(band.until - 1) - first.max(band.from) + 1;
+                    (units.max(0), band.value)
+                })
+                .collect(),
+        };
+        let quantity = covered.iter().map(|(units, _)| units).sum();
+        if quantity == 0 {
+            continue;
+        }
+        let price = line.price().max(Decimal::ZERO);
+        let amount: Decimal = covered
+            .iter()
+            .map(|(units, value)| {
+                let units = Decimal::from(*units);
+                match value_type {
+                    TierValueType::Percentage => decimal::percent_of(price * units, *value),
+                    TierValueType::FixedAmount => (*value).min(price) * units,
+                }
+            })
+            .sum();
+        let amount = cart.currency.round(amount);
+        if !amount.is_zero() {
+            allocations.push(LineAllocation {
+                line_id: line.line_id.clone(),
+                quantity,
+                amount: decimal::to_bson(amount),
+            });
+        }
+    }
+    allocations
+}
+
+/// Prices a spend tiered action against what is spent on the lines `items`
+/// selects. The saving never exceeds that spend.
+pub(crate) fn spend_discount(
+    cart: &Cart,
+    items: &ItemSelector,
+    value_type: TierValueType,
+    tiers: &[SpendTier],
+    mode: TierMode,
+) -> Decimal {
+    let spend: Decimal = cart
+        .lines
+        .iter()
+        .filter(|line| items.matches(line))
+        .map(|line| line.subtotal().max(Decimal::ZERO))
+        .sum();
+    let bands = bands(
+        tiers.iter().map(|t| (t.min_spend.value(), t.value)),
+        value_type,
+        Decimal::MAX,
+        cart.currency,
+    );
+    let amount =
+        match mode {
+            TierMode::WholeAtReachedTier => bands
+                .iter()
+                .rev()
+                .find(|band| band.from <= spend)
+                .map_or(Decimal::ZERO, |band| match value_type {
+                    TierValueType::Percentage => decimal::percent_of(spend, band.value),
+                    TierValueType::FixedAmount => band.value,
+                }),
+            TierMode::Graduated => bands
+                .iter()
+                .filter(|band| band.from <= spend)
+                .map(|band| match value_type {
+                    TierValueType::Percentage => {
+                        decimal::percent_of(band.until.min(spend) - band.from, band.value)
+                    }
+                    TierValueType::FixedAmount => band.value,
+                })
+                .sum(),
+        };
+    cart.currency.round(amount).min(spend)
+}
+
+/// One tier with the threshold where the next one takes over.
+struct Band<T> {
+    from: T,
+    until: T,
+    value: Decimal,
+}
+
+/// Turns tiers, given as threshold and value in ascending order, into
+/// bands, clamping values the way the other actions do. Fixed amounts are
+/// rounded to whole minor units of `currency`.
+fn bands<T: Copy>(
+    tiers: impl Iterator<Item = (T, Decimal128)>,
+    value_type: TierValueType,
+    open_end: T,
+    currency: Currency,
+) -> Vec<Band<T>> {
+    let tiers: Vec<_> = tiers.collect();
+    tiers
+        .iter()
+        .enumerate()
+        .map(|(i, (from, value))| {
+            let value = decimal::from_bson_or_zero(*value).max(Decimal::ZERO);
+            Band {
+                from: *from,
+                until: tiers.get(i + 1).map_or(open_end, |(next, _)| *next),
+                value: match value_type {
+                    TierValueType::Percentage => value.min(Decimal::ONE_HUNDRED),
+                    TierValueType::FixedAmount => currency.round(value),
+                },
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{cart, d128, dec, line, usd};
+
+    fn quantity_tiers(tiers: &[(i32, &str)]) -> Vec<QuantityTier> {
+        tiers
+            .iter()
+            .map(|(min_quantity, value)| QuantityTier {
+                min_quantity: *min_quantity,
+                value: d128(value),
+            })
+            .collect()
+    }
+
+    fn spend_tiers(tiers: &[(&str, &str)]) -> Vec<SpendTier> {
+        tiers
+            .iter()
+            .map(|(min_spend, value)| SpendTier {
+                min_spend: usd(min_spend),
+                value: d128(value),
+            })
+            .collect()
+    }
+
+    fn discounted(allocations: &[LineAllocation]) -> Vec<(&str, i32, Decimal)> {
+        allocations
+            .iter()
+            .map(|a| {
+                (
+                    a.line_id.as_str(),
+                    a.quantity,
+                    decimal::from_bson_or_zero(a.amount),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_graduated_quantity_tiers_price_each_band_separately() {
+        let cart = cart(vec![line("a", "a", "10", 3), line("b", "b", "10", 4)]);
+        let tiers = quantity_tiers(&[(3, "10"), (6, "50")]);
+        let allocations = allocate_quantity(
+            &cart,
+            &ItemSelector::default(),
+            TierValueType::Percentage,
+            &tiers,
+            TierMode::Graduated,
+        );
+        // Units 1-2 pay full price, 3-5 get 10% off, 6-7 get 50% off.
+        assert_eq!(
+            discounted(&allocations),
+            vec![("a", 1, dec("1")), ("b", 4, dec("12"))]
+        );
+    }
+
+    #[test]
+    fn test_whole_cart_gets_the_reached_quantity_tier() {
+        let cart = cart(vec![line("a", "a", "10", 3), line("b", "b", "10", 4)]);
+        let tiers = quantity_tiers(&[(3, "1"), (6, "2")]);
+        let allocations = allocate_quantity(
+            &cart,
+            &ItemSelector::default(),
+            TierValueType::FixedAmount,
+            &tiers,
+            TierMode::WholeAtReachedTier,
+        );
+        assert_eq!(
+            discounted(&allocations),
+            vec![("a", 3, dec("6")), ("b", 4, dec("8"))]
+        );
+    }
+
+    #[test]
+    fn test_quantity_tiers_count_only_selected_units() {
+        let cart = cart(vec![line("a", "a", "10", 2), line("b", "b", "10", 5)]);
+        let allocations = allocate_quantity(
+            &cart,
+            &ItemSelector::products(vec!["a".to_string()]),
+            TierValueType::Percentage,
+            &quantity_tiers(&[(3, "10")]),
+            TierMode::WholeAtReachedTier,
+        );
+        assert!(allocations.is_empty());
+    }
+
+    #[test]
+    fn test_graduated_spend_tiers_add_up_per_band() {
+        let cart = cart(vec![line("a", "a", "250", 1)]);
+        let percent = spend_discount(
+            &cart,
+            &ItemSelector::default(),
+            TierValueType::Percentage,
+            &spend_tiers(&[("100", "10"), ("200", "20")]),
+            TierMode::Graduated,
+        );
+        // 10% of 100-200 plus 20% of 200-250.
+        assert_eq!(percent, dec("20"));
+
+        let fixed = spend_discount(
+            &cart,
+            &ItemSelector::default(),
+            TierValueType::FixedAmount,
+            &spend_tiers(&[("100", "10"), ("200", "30"), ("300", "50")]),
+            TierMode::Graduated,
+        );
+        assert_eq!(fixed, dec("40"));
+    }
+
+    #[test]
+    fn test_spend_tier_saving_never_exceeds_the_spend() {
+        let cart = cart(vec![line("a", "a", "20", 1)]);
+        let saving = spend_discount(
+            &cart,
+            &ItemSelector::default(),
+            TierValueType::FixedAmount,
+            &spend_tiers(&[("10", "25")]),
+            TierMode::WholeAtReachedTier,
+        );
+        assert_eq!(saving, dec("20"));
+    }
+
+    #[test]
+    fn test_huge_quantities_do_not_overflow_the_unit_count() {
+        let cart = cart(vec![line("a", "a", "1", i32::MAX), line("b", "b", "1", 1)]);
+        let tiers = quantity_tiers(&[(3, "0.5")]);
+        let whole = allocate_quantity(
+            &cart,
+            &ItemSelector::default(),
+            TierValueType::FixedAmount,
+            &tiers,
+            TierMode::WholeAtReachedTier,
+        );
+        assert_eq!(
+            discounted(&whole),
+            vec![("a", i32::MAX, dec("1073741823.50")), ("b", 1, dec("0.50"))]
+        );
+        let graduated = allocate_quantity(
+            &cart,
+            &ItemSelector::default(),
+            TierValueType::FixedAmount,
+            &tiers,
+            TierMode::Graduated,
+        );
+        // The open top band ends just short of `i32::MAX`.
+        assert_eq!(discounted(&graduated)[0].1, i32::MAX - 3);
+    }
+}
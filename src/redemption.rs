@@ -0,0 +1,39 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Entity;
+
+/// Identifies one customer's use of one rule or coupon in a shop.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RedemptionKey {
+    pub shop_id: String,
+    pub customer_id: String,
+    pub entity: Entity,
+    pub entity_id: String,
+}
+
+/// Ledger entry counting how often a customer has redeemed a rule or coupon.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Redemption {
+    #[serde(flatten)]
+    pub key: RedemptionKey,
+    pub count: i32,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub updated_at: DateTime<Utc>,
+}
+
+impl RedemptionKey {
+    pub fn new(
+        shop_id: impl Into<String>,
+        customer_id: impl Into<String>,
+        entity: Entity,
+        entity_id: impl Into<String>,
+    ) -> Self {
+        Self {
+            shop_id: shop_id.into(),
+            customer_id: customer_id.into(),
+            entity,
+            entity_id: entity_id.into(),
+        }
+    }
+}
@@ -0,0 +1,411 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+use crate::{datetime::datetime_serialization, error::FieldError};
+
+/// How far ahead [`Recurrence::windows_after`] looks before giving up.
+const SEARCH_HORIZON_DAYS: i64 = 366 * 5;
+
+/// The longest window an occurrence may open: a year, the longest period.
+const MAX_DURATION_MINUTES: u32 = 366 * 24 * 60;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Frequency {
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// A day of the week (0 for Sunday through 6 for Saturday), optionally only
+/// its `nth` occurrence in the month: 1 for the first, -1 for the last.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct WeekdayRule {
+    pub day: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nth: Option<i32>,
+}
+
+/// Local dates, both inclusive, on which the rule never applies.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BlackoutPeriod {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+}
+
+/// An RRULE-like schedule, read in the shop's time zone.
+///
+/// A local date occurs when it is on or after `starts_on`, falls in a period
+/// selected by `frequency` and `interval`, and passes every non-empty `by_*`
+/// filter. With no filters the date's position in `starts_on` is repeated:
+/// the same weekday, day of month or day of year. Each occurrence opens a
+/// window at `start_hour:start_minute` lasting `duration_minutes`, which may
+/// run past midnight, so "first weekend of the month" is a monthly first
+/// Saturday lasting two days.
+///
+/// `exclusions` drop single occurrences by their start date; `blackouts`
+/// switch the rule off for whole local dates, even inside a window.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Recurrence {
+    pub frequency: Frequency,
+    #[serde(default = "default_interval")]
+    pub interval: u32,
+    pub starts_on: NaiveDate,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub by_month: Vec<u32>,
+    /// Days of the month; negative values count from the end, -1 being the
+    /// last day.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub by_month_day: Vec<i32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub by_weekday: Vec<WeekdayRule>,
+    #[serde(default)]
+    pub start_hour: u32,
+    #[serde(default)]
+    pub start_minute: u32,
+    #[serde(default = "default_duration")]
+    pub duration_minutes: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclusions: Vec<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blackouts: Vec<BlackoutPeriod>,
+}
+
+fn default_interval() -> u32 {
+    1
+}
+
+fn default_duration() -> u32 {
+    24 * 60
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ActivationWindow {
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub starts_at: DateTime<Utc>,
+    /// `None` when the window never closes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, with = "datetime_serialization")]
+    pub ends_at: Option<DateTime<Utc>>,
+}
+
+impl Recurrence {
+    pub fn occurs_on(&self, date: NaiveDate) -> bool {
+        if date < self.starts_on || !self.in_selected_period(date) {
+            return false;
+        }
+        let filtered = !self.by_month.is_empty()
+            || !self.by_month_day.is_empty()
+            || !self.by_weekday.is_empty();
+        if !filtered {
+            return match self.frequency {
+                Frequency::Weekly => date.weekday() == self.starts_on.weekday(),
+                Frequency::Monthly => date.day() == self.starts_on.day(),
+                Frequency::Yearly => {
+                    date.month() == self.starts_on.month() && date.day() == self.starts_on.day()
+                }
+            };
+        }
+        (self.by_month.is_empty() || self.by_month.contains(&date.month()))
+            && (self.by_month_day.is_empty()
+                || self
+                    .by_month_day
+                    .iter()
+                    .any(|d| month_day_matches(date, *d)))
+            && (self.by_weekday.is_empty() || self.by_weekday.iter().any(|w| w.matches(date)))
+    }
+
+    pub fn is_blacked_out(&self, date: NaiveDate) -> bool {
+        self.blackouts
+            .iter()
+            .any(|b| b.start <= date && date <= b.end)
+    }
+
+    /// Whether `at` falls inside an occurrence's window and outside every
+    /// blackout, reading dates in `timezone`.
+    pub fn is_active_at(&self, at: DateTime<Utc>, timezone: Tz) -> bool {
+        let local_date = at.with_timezone(&timezone).date_naive();
+        if self.is_blacked_out(local_date) {
+            return false;
+        }
+        let lookback = i64::from(self.duration_minutes.div_ceil(24 * 60));
+        (0..=lookback)
+            .filter_map(|back| local_date.checked_sub_signed(Duration::days(back)))
+            .filter_map(|date| self.window_on(date, timezone))
+            .any(|window| window.starts_at <= at && window.ends_at.is_none_or(|end| at < end))
+    }
+
+    /// Up to `count` windows that have not ended by `after`, in order.
+    /// Occurrences starting on a blackout date are left out.
+    pub fn windows_after(
+        &self,
+        after: DateTime<Utc>,
+        timezone: Tz,
+        count: usize,
+    ) -> Vec<ActivationWindow> {
+        let lookback = i64::from(self.duration_minutes.div_ceil(24 * 60));
+        let first = after.with_timezone(&timezone).date_naive() - Duration::days(lookback);
+        first
+            .iter_days()
+            .take((SEARCH_HORIZON_DAYS + lookback) as usize)
+            .filter(|date| !self.is_blacked_out(*date))
+            .filter_map(|date| self.window_on(date, timezone))
+            .filter(|window| window.ends_at.is_none_or(|end| end > after))
+            .take(count)
+            .collect()
+    }
+
+    /// Problems with the recurrence, reported under `recurrence.*` fields.
+    pub fn field_errors(&self) -> Vec<FieldError> {
+        let mut fields = Vec::new();
+        let mut check = |ok: bool, field: &str, message: &str| {
+            if !ok {
+                fields.push(FieldError::new(format!("recurrence.{field}"), message));
+            }
+        };
+        check(self.interval > 0, "interval", "must be positive");
+        check(
+            self.by_month.iter().all(|m| (1..=12).contains(m)),
+            "by_month",
+            "months must be between 1 and 12",
+        );
+        check(
+            self.by_month_day
+                .iter()
+                .all(|d| (1..=31).contains(&d.abs())),
+            "by_month_day",
+            "days must be between 1 and 31 or -31 and -1",
+        );
+        check(
+            self.by_weekday
+                .iter()
+                .all(|w| w.day <= 6 && w.nth.is_none_or(|n| (1..=5).contains(&n.abs()))),
+            "by_weekday",
+            "day must be between 0 and 6 and nth between 1 and 5 or -5 and -1",
+        );
+        check(self.start_hour < 24, "start_hour", "must be below 24");
+        check(self.start_minute < 60, "start_minute", "must be below 60");
+        check(
+            (1..=MAX_DURATION_MINUTES).contains(&self.duration_minutes),
+            "duration_minutes",
+            "must be positive and at most a year",
+        );
+        check(
+            self.blackouts.iter().all(|b| b.start <= b.end),
+            "blackouts",
+            "end must not be before start",
+        );
+        fields
+    }
+
+    fn window_on(&self, date: NaiveDate, timezone: Tz) -> Option<ActivationWindow> {
+        if !self.occurs_on(date) || self.exclusions.contains(&date) {
+            return None;
+        }
+        let time = NaiveTime::from_hms_opt(self.start_hour, self.start_minute, 0)?;
+        let starts_at = resolve_local(date.and_time(time), timezone)?;
+        Some(ActivationWindow {
+            starts_at,
+            ends_at: Some(starts_at + Duration::minutes(i64::from(self.duration_minutes))),
+        })
+    }
+
+    fn in_selected_period(&self, date: NaiveDate) -> bool {
+        let interval = i64::from(self.interval.max(1));
+        let elapsed = match self.frequency {
+            Frequency::Weekly => {
+                let week_start = |d: NaiveDate| {
+                    d - Duration::days(i64::from(d.weekday().num_days_from_sunday()))
+                };
+                (week_start(date) - week_start(self.starts_on)).num_days() / 7
+            }
+            Frequency::Monthly => {
+                i64::from(date.year() - self.starts_on.year()) * 12 + i64::from(date.month())
+                    - i64::from(self.starts_on.month())
+            }
+            Frequency::Yearly => i64::from(date.year() - self.starts_on.year()),
+        };
+        elapsed % interval == 0
+    }
+}
+
+impl WeekdayRule {
+    pub fn matches(&self, date: NaiveDate) -> bool {
+        if date.weekday().num_days_from_sunday() as u8 != self.day {
+            return false;
+        }
+        match self.nth {
+            None => true,
+            Some(nth) if nth > 0 => (date.day() as i32 - 1) / 7 + 1 == nth,
+            Some(nth) => (days_in_month(date) as i32 - date.day() as i32) / 7 + 1 == -nth,
+        }
+    }
+}
+
+fn month_day_matches(date: NaiveDate, day: i32) -> bool {
+    if day > 0 {
+        date.day() as i32 == day
+    } else {
+        days_in_month(date) as i32 + day + 1 == date.day() as i32
+    }
+}
+
+fn days_in_month(date: NaiveDate) -> u32 {
+    let first_of_next = if date.month() == 12 {
+        NaiveDate::from_ymd_opt(date.year() + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd_opt(date.year(), date.month() + 1, 1)
+    };
+    first_of_next
+        .and_then(|d| d.pred_opt())
+        .map_or(31, |last| last.day())
+}
+
+/// Local wall time to UTC. Ambiguous times take the earlier instant and
+/// times skipped by a DST jump move forward by the size of the gap.
+fn resolve_local(local: NaiveDateTime, timezone: Tz) -> Option<DateTime<Utc>> {
+    timezone
+        .from_local_datetime(&local)
+        .earliest()
+        .or_else(|| {
+            timezone
+                .from_local_datetime(&(local + Duration::hours(1)))
+                .earliest()
+        })
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+    }
+
+    fn recurrence(frequency: Frequency, starts_on: NaiveDate) -> Recurrence {
+        Recurrence {
+            frequency,
+            interval: 1,
+            starts_on,
+            by_month: Vec::new(),
+            by_month_day: Vec::new(),
+            by_weekday: Vec::new(),
+            start_hour: 0,
+            start_minute: 0,
+            duration_minutes: default_duration(),
+            exclusions: Vec::new(),
+            blackouts: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_overnight_window_runs_past_local_midnight() {
+        // Fridays 22:00 to 02:00 in Berlin, an hour ahead of UTC in winter.
+        let mut friday_nights = recurrence(Frequency::Weekly, date(2024, 1, 5));
+        friday_nights.start_hour = 22;
+        friday_nights.duration_minutes = 4 * 60;
+        let berlin = chrono_tz::Europe::Berlin;
+
+        assert!(!friday_nights.is_active_at(utc(2024, 1, 12, 20, 59), berlin));
+        assert!(friday_nights.is_active_at(utc(2024, 1, 12, 21, 0), berlin));
+        assert!(friday_nights.is_active_at(utc(2024, 1, 13, 0, 30), berlin));
+        assert!(!friday_nights.is_active_at(utc(2024, 1, 13, 1, 0), berlin));
+        assert!(friday_nights.is_active_at(utc(2024, 1, 12, 21, 30), berlin));
+        assert!(!friday_nights.is_active_at(utc(2024, 1, 12, 21, 30), Tz::UTC));
+    }
+
+    #[test]
+    fn test_nth_weekday_and_negative_month_day() {
+        let mut last_friday = recurrence(Frequency::Monthly, date(2024, 1, 1));
+        last_friday.by_weekday = vec![WeekdayRule {
+            day: 5,
+            nth: Some(-1),
+        }];
+        assert!(last_friday.occurs_on(date(2024, 1, 26)));
+        assert!(!last_friday.occurs_on(date(2024, 1, 19)));
+        assert!(last_friday.occurs_on(date(2024, 2, 23)));
+
+        let mut month_end = recurrence(Frequency::Monthly, date(2024, 1, 1));
+        month_end.by_month_day = vec![-1];
+        assert!(month_end.occurs_on(date(2024, 2, 29)));
+        assert!(!month_end.occurs_on(date(2024, 2, 28)));
+    }
+
+    #[test]
+    fn test_interval_skips_periods() {
+        let fortnightly = Recurrence {
+            interval: 2,
+            ..recurrence(Frequency::Weekly, date(2024, 1, 1))
+        };
+        assert!(fortnightly.occurs_on(date(2024, 1, 1)));
+        assert!(!fortnightly.occurs_on(date(2024, 1, 8)));
+        assert!(fortnightly.occurs_on(date(2024, 1, 15)));
+        assert!(!fortnightly.occurs_on(date(2023, 12, 18)));
+    }
+
+    #[test]
+    fn test_windows_skip_exclusions_and_blackouts() {
+        let mut mondays = recurrence(Frequency::Weekly, date(2024, 1, 1));
+        mondays.exclusions = vec![date(2024, 1, 8)];
+        mondays.blackouts = vec![BlackoutPeriod {
+            start: date(2024, 1, 14),
+            end: date(2024, 1, 16),
+        }];
+
+        let starts: Vec<_> = mondays
+            .windows_after(utc(2024, 1, 2, 0, 0), Tz::UTC, 2)
+            .into_iter()
+            .map(|w| w.starts_at)
+            .collect();
+        assert_eq!(starts, vec![utc(2024, 1, 22, 0, 0), utc(2024, 1, 29, 0, 0)]);
+        assert!(!mondays.is_active_at(utc(2024, 1, 15, 12, 0), Tz::UTC));
+    }
+
+    #[test]
+    fn test_start_skipped_by_dst_moves_forward() {
+        let mut sundays = recurrence(Frequency::Weekly, date(2024, 3, 3));
+        sundays.start_hour = 2;
+        sundays.start_minute = 30;
+        sundays.duration_minutes = 60;
+
+        let starts: Vec<_> = sundays
+            .windows_after(utc(2024, 3, 9, 0, 0), chrono_tz::America::New_York, 2)
+            .into_iter()
+            .map(|w| w.starts_at)
+            .collect();
+        assert_eq!(
+            starts,
+            vec![utc(2024, 3, 10, 7, 30), utc(2024, 3, 17, 6, 30)]
+        );
+    }
+
+    #[test]
+    fn test_field_errors_name_each_bad_field() {
+        let mut bad = recurrence(Frequency::Monthly, date(2024, 1, 1));
+        bad.interval = 0;
+        bad.by_month = vec![13];
+        bad.start_hour = 24;
+        bad.duration_minutes = MAX_DURATION_MINUTES + 1;
+        let fields: Vec<String> = bad.field_errors().into_iter().map(|f| f.field).collect();
+        assert_eq!(
+            fields,
+            vec![
+                "recurrence.interval",
+                "recurrence.by_month",
+                "recurrence.start_hour",
+                "recurrence.duration_minutes",
+            ]
+        );
+        bad.duration_minutes = u32::MAX;
+        assert!(
+            bad.field_errors()
+                .iter()
+                .any(|f| f.field == "recurrence.duration_minutes")
+        );
+    }
+}
@@ -0,0 +1,3 @@
+pub mod coupon;
+pub mod discount;
+pub mod membership;
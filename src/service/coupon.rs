@@ -0,0 +1,773 @@
+use std::{collections::HashSet, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use mongodb::Database;
+use tarpc::context::Context;
+use uuid::Uuid;
+
+use crate::{
+    coupon::{Coupon, CouponRejection, CouponService},
+    coupon_code::{self, CodeTemplate, MAX_BATCH_SIZE},
+    decimal,
+    error::{Entity, FieldError, ServiceError},
+    money::Money,
+    redemption::RedemptionKey,
+    storage::{
+        CouponRepository, RedemptionRepository, StorageError,
+        mongo::{MongoCouponRepository, MongoRedemptionRepository},
+    },
+};
+
+/// How many random codes a batch may draw per coupon before giving up on
+/// finding unused ones.
+const MAX_DRAWS_PER_CODE: usize = 20;
+
+/// [`CouponService`] over any [`CouponRepository`], with per-customer limits
+/// kept in a [`RedemptionRepository`].
+///
+/// Codes are [normalized](coupon_code::normalize) before they are stored or
+/// looked up. A code claimed by one of the configured code formats must also
+/// pass its check, or it is turned away as mistyped without a lookup.
+/// Coupons stored before codes were normalized are still found by the code
+/// exactly as they were created with.
+pub struct CouponServiceImpl<R, L> {
+    coupons: Arc<R>,
+    redemptions: Arc<L>,
+    code_formats: Arc<Vec<CodeTemplate>>,
+}
+
+impl<R, L> Clone for CouponServiceImpl<R, L> {
+    fn clone(&self) -> Self {
+        Self {
+            coupons: Arc::clone(&self.coupons),
+            redemptions: Arc::clone(&self.redemptions),
+            code_formats: Arc::clone(&self.code_formats),
+        }
+    }
+}
+
+impl<R: CouponRepository, L: RedemptionRepository> CouponServiceImpl<R, L> {
+    pub fn new(coupons: R, redemptions: L) -> Self {
+        Self {
+            coupons: Arc::new(coupons),
+            redemptions: Arc::new(redemptions),
+            code_formats: Arc::new(Vec::new()),
+        }
+    }
+
+    /// Templates that issued codes in this deployment; see
+    /// [`CodeTemplate::claims`].
+    pub fn with_code_formats(mut self, code_formats: Vec<CodeTemplate>) -> Self {
+        self.code_formats = Arc::new(code_formats);
+        self
+    }
+
+    /// Normalizes a code a shopper typed and rejects it if it cannot have
+    /// been issued.
+    fn screen_code(&self, code: &str) -> Result<String, ServiceError> {
+        let code = coupon_code::normalize(code);
+        if code.is_empty() {
+            return Err(ServiceError::invalid_field(
+                "coupon_code",
+                "must not be empty",
+            ));
+        }
+        let claimed: Vec<&CodeTemplate> = self
+            .code_formats
+            .iter()
+            .filter(|t| t.claims(&code))
+            .collect();
+        if !claimed.is_empty() && !claimed.iter().any(|t| t.accepts(&code)) {
+            return Err(ServiceError::MistypedCode { code });
+        }
+        Ok(code)
+    }
+
+    /// Finds the coupon a shopper typed as `typed`. When the normalized code
+    /// matches nothing, the code is looked up again as typed, for coupons
+    /// stored before codes were normalized.
+    async fn find_by_code(
+        &self,
+        typed: &str,
+        shop_id: Option<&str>,
+    ) -> Result<Coupon, ServiceError> {
+        let code = self.screen_code(typed)?;
+        if let Some(coupon) = self.coupons.find_by_code(&code, shop_id).await? {
+            return Ok(coupon);
+        }
+        let legacy = typed.trim();
+        if legacy != code
+            && let Some(coupon) = self.coupons.find_by_code(legacy, shop_id).await?
+        {
+            return Ok(coupon);
+        }
+        Err(ServiceError::not_found(Entity::Coupon, code))
+    }
+
+    /// Uses up one redemption of `coupon`, explaining the refusal when the
+    /// repository would not redeem it. A known customer's
+    /// ledger entry is taken first and given back if the coupon itself
+    /// cannot be redeemed.
+    async fn redeem(
+        &self,
+        coupon: &Coupon,
+        customer_id: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> Result<Coupon, ServiceError> {
+        let code = &coupon.code;
+        let entity = Entity::Coupon;
+        let ledger_key = match customer_id {
+            Some(customer_id) => Some(RedemptionKey::new(
+                &coupon.shop_id,
+                customer_id,
+                entity,
+                &coupon.id,
+            )),
+            None if coupon.max_uses_per_customer.is_some() => {
+                return Err(ServiceError::invalid_field(
+                    "customer_id",
+                    "required for coupons limited per customer",
+                ));
+            }
+            None => None,
+        };
+        if let Some(key) = &ledger_key
+            && self
+                .redemptions
+                .record(key, coupon.max_uses_per_customer, now)
+                .await?
+                .is_none()
+        {
+            return Err(ServiceError::UsageLimitReached {
+                entity,
+                key: format!("{code} for customer {}", key.customer_id),
+            });
+        }
+        let redeemed = self.coupons.redeem(&coupon.id, now).await;
+        if let Ok(Some(coupon)) = redeemed {
+            return Ok(coupon);
+        }
+        if let Some(key) = &ledger_key {
+            let _ = self.redemptions.release(key, now).await;
+        }
+        redeemed?;
+        let rejection = coupon
+            .availability_at(now)
+            .err()
+            .unwrap_or(CouponRejection::UsageLimitReached);
+        Err(rejection.to_service_error(code))
+    }
+
+    /// `count` codes from `template`, distinct from each other and from every
+    /// code already in the shop. Codes that clash are drawn again, up to
+    /// [`MAX_DRAWS_PER_CODE`] draws per code, after which the template is
+    /// taken to be used up in the shop.
+    async fn unique_codes(
+        &self,
+        shop_id: &str,
+        template: &CodeTemplate,
+        count: usize,
+    ) -> Result<Vec<String>, ServiceError> {
+        let mut taken: HashSet<String> = HashSet::with_capacity(count);
+        let mut codes: Vec<String> = Vec::with_capacity(count);
+        let mut draws_left = count.saturating_mul(MAX_DRAWS_PER_CODE);
+        while codes.len() < count {
+            let mut fresh = Vec::with_capacity(count - codes.len());
+            {
+                let mut rng = rand::thread_rng();
+                while fresh.len() < count - codes.len() {
+                    if draws_left == 0 {
+                        return Err(ServiceError::Conflict(format!(
+                            "too few unused codes left for template {}* in shop {shop_id}",
+                            template.prefix
+                        )));
+                    }
+                    draws_left -= 1;
+                    let code = template.generate(&mut rng);
+                    if taken.insert(code.clone()) {
+                        fresh.push(code);
+                    }
+                }
+            }
+            let existing: HashSet<String> = self
+                .coupons
+                .existing_codes(shop_id, &fresh)
+                .await?
+                .into_iter()
+                .collect();
+            codes.extend(fresh.into_iter().filter(|c| !existing.contains(c)));
+        }
+        Ok(codes)
+    }
+}
+
+impl CouponServiceImpl<MongoCouponRepository, MongoRedemptionRepository> {
+    /// Serves coupons from the `coupons` collection in `db`, with the ledger
+    /// in `redemptions`.
+    pub async fn mongo(db: &Database) -> mongodb::error::Result<Self> {
+        Ok(Self::new(
+            MongoCouponRepository::new(db).await?,
+            MongoRedemptionRepository::new(db).await?,
+        ))
+    }
+}
+
+fn write_error(coupon: &Coupon, error: StorageError) -> ServiceError {
+    match error {
+        StorageError::Duplicate(_) => ServiceError::Conflict(format!(
+            "coupon code {} already exists in shop {}",
+            coupon.code, coupon.shop_id
+        )),
+        e => e.into(),
+    }
+}
+
+impl<R: CouponRepository, L: RedemptionRepository> CouponService for CouponServiceImpl<R, L> {
+    async fn create_coupon(self, _: Context, mut coupon: Coupon) -> Result<Coupon, ServiceError> {
+        if coupon.id.is_empty() {
+            coupon.id = Uuid::new_v4().to_string();
+        }
+        coupon.code = coupon_code::normalize(&coupon.code);
+        coupon.validate()?;
+        let now = Utc::now();
+        coupon.created_at = now;
+        coupon.updated_at = now;
+        self.coupons
+            .insert(&coupon)
+            .await
+            .map_err(|e| write_error(&coupon, e))?;
+        Ok(coupon)
+    }
+
+    async fn get_coupon(self, _: Context, id: String) -> Result<Coupon, ServiceError> {
+        self.coupons
+            .get(&id)
+            .await?
+            .ok_or_else(|| ServiceError::not_found(Entity::Coupon, id))
+    }
+
+    async fn update_coupon(self, _: Context, mut coupon: Coupon) -> Result<Coupon, ServiceError> {
+        coupon.code = coupon_code::normalize(&coupon.code);
+        coupon.validate()?;
+        let existing = self
+            .coupons
+            .get(&coupon.id)
+            .await?
+            .ok_or_else(|| ServiceError::not_found(Entity::Coupon, &coupon.id))?;
+        if coupon.code == coupon_code::normalize(&existing.code) {
+            // Left as stored: a code saved before codes were normalized is
+            // only rewritten when the update changes it.
+            coupon.code = existing.code;
+        }
+        coupon.created_at = existing.created_at;
+        // Uses are only counted through redemption, never by an update.
+        coupon.used_count = existing.used_count;
+        coupon.updated_at = Utc::now();
+        if !self
+            .coupons
+            .replace(&coupon)
+            .await
+            .map_err(|e| write_error(&coupon, e))?
+        {
+            return Err(ServiceError::not_found(Entity::Coupon, coupon.id));
+        }
+        Ok(coupon)
+    }
+
+    async fn delete_coupon(self, _: Context, id: String) -> Result<(), ServiceError> {
+        if !self.coupons.delete(&id).await? {
+            return Err(ServiceError::not_found(Entity::Coupon, id));
+        }
+        Ok(())
+    }
+
+    async fn list_coupons(self, _: Context, shop_id: String) -> Result<Vec<Coupon>, ServiceError> {
+        Ok(self.coupons.list_by_shop(&shop_id).await?)
+    }
+
+    async fn apply_coupon(
+        self,
+        _: Context,
+        coupon_code: String,
+        shop_id: String,
+        customer_id: Option<String>,
+        cart_total: Money,
+    ) -> Result<Money, ServiceError> {
+        let total = decimal::from_bson(cart_total.amount)
+            .map_err(|e| ServiceError::invalid_decimal("cart_total", e))?;
+        let coupon = self.find_by_code(&coupon_code, Some(&shop_id)).await?;
+        if let Err(rejection) = coupon
+            .check_currency(cart_total.currency)
+            .and_then(|()| coupon.restrictions.check_total(total))
+        {
+            return Err(rejection.to_service_error(&coupon.code));
+        }
+        let coupon = self
+            .redeem(&coupon, customer_id.as_deref(), Utc::now())
+            .await?;
+        Ok(Money::new(
+            coupon.apply_to_total(total),
+            cart_total.currency,
+        ))
+    }
+
+    async fn release_coupon(
+        self,
+        _: Context,
+        coupon_code: String,
+        shop_id: String,
+        customer_id: Option<String>,
+    ) -> Result<Coupon, ServiceError> {
+        let now = Utc::now();
+        let coupon = self.find_by_code(&coupon_code, Some(&shop_id)).await?;
+        let coupon = self
+            .coupons
+            .release(&coupon.id, now)
+            .await?
+            .ok_or_else(|| ServiceError::not_found(Entity::Coupon, coupon.code))?;
+        if let Some(customer_id) = customer_id {
+            let key = RedemptionKey::new(&coupon.shop_id, customer_id, Entity::Coupon, &coupon.id);
+            self.redemptions.release(&key, now).await?;
+        }
+        Ok(coupon)
+    }
+
+    async fn generate_coupon_batch(
+        self,
+        _: Context,
+        campaign_id: String,
+        template: CodeTemplate,
+        count: u32,
+        prototype: Coupon,
+    ) -> Result<Vec<Coupon>, ServiceError> {
+        let mut fields = template.field_errors(count);
+        if campaign_id.trim().is_empty() {
+            fields.push(FieldError::new("campaign_id", "must not be empty"));
+        }
+        if count == 0 || count > MAX_BATCH_SIZE {
+            fields.push(FieldError::new(
+                "count",
+                format!("must be between 1 and {MAX_BATCH_SIZE}"),
+            ));
+        }
+        ServiceError::check_fields(fields)?;
+        let now = Utc::now();
+        let prototype = Coupon {
+            id: String::new(),
+            code: template.generate(&mut rand::thread_rng()),
+            campaign_id: Some(campaign_id),
+            is_single_use: true,
+            used_count: 0,
+            created_at: now,
+            updated_at: now,
+            ..prototype
+        };
+        prototype.validate()?;
+        let codes = self
+            .unique_codes(&prototype.shop_id, &template, count as usize)
+            .await?;
+        let coupons: Vec<Coupon> = codes
+            .into_iter()
+            .map(|code| Coupon {
+                id: Uuid::new_v4().to_string(),
+                code,
+                ..prototype.clone()
+            })
+            .collect();
+        self.coupons
+            .insert_many(&coupons)
+            .await
+            .map_err(|e| match e {
+                StorageError::Duplicate(_) => ServiceError::Conflict(format!(
+                    "a generated code was taken in shop {} while the batch was saved",
+                    prototype.shop_id
+                )),
+                e => e.into(),
+            })?;
+        Ok(coupons)
+    }
+
+    async fn list_campaign_coupons(
+        self,
+        _: Context,
+        shop_id: String,
+        campaign_id: String,
+    ) -> Result<Vec<Coupon>, ServiceError> {
+        Ok(self
+            .coupons
+            .list_by_campaign(&shop_id, &campaign_id)
+            .await?)
+    }
+
+    async fn validate_coupon(
+        self,
+        _: Context,
+        coupon_code: String,
+        shop_id: String,
+    ) -> Result<bool, ServiceError> {
+        let coupon = self.find_by_code(&coupon_code, Some(&shop_id)).await?;
+        Ok(coupon.is_redeemable_at(Utc::now()))
+    }
+
+    async fn get_coupon_by_code(
+        self,
+        _: Context,
+        coupon_code: String,
+    ) -> Result<Coupon, ServiceError> {
+        self.find_by_code(&coupon_code, None).await
+    }
+
+    async fn get_coupon_by_code_and_shop(
+        self,
+        _: Context,
+        coupon_code: String,
+        shop_id: String,
+    ) -> Result<Coupon, ServiceError> {
+        self.find_by_code(&coupon_code, Some(&shop_id)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tarpc::context;
+
+    use super::*;
+    use crate::{
+        fixtures::{coupon, dec, usd},
+        storage::memory::{InMemoryCouponRepository, InMemoryRedemptionRepository},
+    };
+
+    type Service = CouponServiceImpl<InMemoryCouponRepository, InMemoryRedemptionRepository>;
+
+    fn service() -> Service {
+        CouponServiceImpl::new(
+            InMemoryCouponRepository::new(),
+            InMemoryRedemptionRepository::new(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_single_use_coupon_applies_once() {
+        let service = service();
+        let mut once = coupon("a", "shop-1", "ONCE", "10");
+        once.is_single_use = true;
+        service.coupons.insert(&once).await.unwrap();
+        let apply = || {
+            service.clone().apply_coupon(
+                context::current(),
+                "ONCE".to_string(),
+                "shop-1".to_string(),
+                None,
+                usd("100"),
+            )
+        };
+
+        assert_eq!(apply().await.unwrap(), usd("90"));
+        assert_eq!(
+            apply().await,
+            Err(ServiceError::UsageLimitReached {
+                entity: Entity::Coupon,
+                key: "ONCE".to_string(),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_used_up_coupon_gives_back_the_customers_ledger_entry() {
+        let service = service();
+        let mut used_up = coupon("a", "shop-1", "SAVE", "10");
+        used_up.max_uses = Some(1);
+        used_up.used_count = 1;
+        used_up.max_uses_per_customer = Some(1);
+        service.coupons.insert(&used_up).await.unwrap();
+
+        let result = service
+            .clone()
+            .apply_coupon(
+                context::current(),
+                "SAVE".to_string(),
+                "shop-1".to_string(),
+                Some("customer-1".to_string()),
+                usd("100"),
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(ServiceError::UsageLimitReached { .. })
+        ));
+        let ledger = service
+            .redemptions
+            .list_for_customer("shop-1", "customer-1")
+            .await
+            .unwrap();
+        assert!(ledger.iter().all(|entry| entry.count == 0));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_applications_respect_the_per_customer_limit() {
+        let service = service();
+        let mut once = coupon("a", "shop-1", "ONCE", "10");
+        once.max_uses_per_customer = Some(1);
+        service.coupons.insert(&once).await.unwrap();
+
+        let attempts: Vec<_> = (0..20)
+            .map(|_| {
+                let service = service.clone();
+                tokio::spawn(service.apply_coupon(
+                    context::current(),
+                    "ONCE".to_string(),
+                    "shop-1".to_string(),
+                    Some("customer-1".to_string()),
+                    usd("100"),
+                ))
+            })
+            .collect();
+        let mut applied = 0;
+        for attempt in attempts {
+            match attempt.await.unwrap() {
+                Ok(total) => {
+                    assert_eq!(total.value(), dec("90"));
+                    applied += 1;
+                }
+                Err(e) => assert!(matches!(e, ServiceError::UsageLimitReached { .. })),
+            }
+        }
+        assert_eq!(applied, 1);
+        assert_eq!(
+            service.coupons.get("a").await.unwrap().unwrap().used_count,
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batch_codes_are_unique_single_use_and_linked_to_the_campaign() {
+        let service = service();
+        let batch = service
+            .clone()
+            .generate_coupon_batch(
+                context::current(),
+                "spring".to_string(),
+                CodeTemplate::new("SPRING", 6).with_checksum(),
+                50,
+                coupon("", "shop-1", "", "10"),
+            )
+            .await
+            .unwrap();
+
+        let codes: HashSet<&str> = batch.iter().map(|c| c.code.as_str()).collect();
+        assert_eq!(codes.len(), 50);
+        assert!(
+            batch
+                .iter()
+                .all(|c| c.is_single_use && c.code.starts_with("SPRING"))
+        );
+        let listed = service
+            .clone()
+            .list_campaign_coupons(
+                context::current(),
+                "shop-1".to_string(),
+                "spring".to_string(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(listed.len(), 50);
+    }
+
+    #[tokio::test]
+    async fn test_codes_are_normalized_and_typos_turned_away() {
+        let template = CodeTemplate::new("XMAS", 4).with_checksum();
+        let service = service().with_code_formats(vec![template.clone()]);
+        let code = template.generate(&mut rand::thread_rng());
+        service
+            .coupons
+            .insert(&coupon("a", "shop-1", &code, "10"))
+            .await
+            .unwrap();
+        let lookup = |typed: String| {
+            service
+                .clone()
+                .get_coupon_by_code(context::current(), typed)
+        };
+
+        let typed = format!(" {}-{} ", &code[..4], code[4..].to_lowercase());
+        assert_eq!(lookup(typed).await.unwrap().id, "a");
+        let mut mistyped = code.clone();
+        let last = mistyped.pop().unwrap();
+        mistyped.push(if last == 'A' { 'B' } else { 'A' });
+        assert_eq!(
+            lookup(mistyped.clone()).await.unwrap_err(),
+            ServiceError::MistypedCode { code: mistyped }
+        );
+        assert!(matches!(
+            lookup("XMAS".to_string()).await,
+            Err(ServiceError::NotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_restricted_coupon_needs_a_cart() {
+        let service = service();
+        let mut socks_only = coupon("a", "shop-1", "SOCKS", "10");
+        socks_only.restrictions.product_ids = vec!["socks".to_string()];
+        service.coupons.insert(&socks_only).await.unwrap();
+
+        let result = service
+            .clone()
+            .apply_coupon(
+                context::current(),
+                "SOCKS".to_string(),
+                "shop-1".to_string(),
+                None,
+                usd("100"),
+            )
+            .await;
+        assert!(matches!(result, Err(ServiceError::NotApplicable { .. })));
+        let stored = service.coupons.get("a").await.unwrap().unwrap();
+        assert_eq!(stored.used_count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_used_up_coupon_does_not_consume_another_shops_code() {
+        let service = service();
+        let mut used_up = coupon("a", "shop-a", "SAVE", "10");
+        used_up.max_uses = Some(1);
+        used_up.used_count = 1;
+        service.coupons.insert(&used_up).await.unwrap();
+        service
+            .coupons
+            .insert(&coupon("b", "shop-b", "SAVE", "10"))
+            .await
+            .unwrap();
+
+        let total = service
+            .clone()
+            .apply_coupon(
+                context::current(),
+                "SAVE".to_string(),
+                "shop-b".to_string(),
+                None,
+                usd("100"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(total.value(), dec("90"));
+        assert!(
+            !service
+                .clone()
+                .validate_coupon(context::current(), "SAVE".to_string(), "shop-a".to_string())
+                .await
+                .unwrap()
+        );
+
+        let released = service
+            .clone()
+            .release_coupon(
+                context::current(),
+                "SAVE".to_string(),
+                "shop-b".to_string(),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(released.id, "b");
+        assert_eq!(released.used_count, 0);
+        let untouched = service.coupons.get("a").await.unwrap().unwrap();
+        assert_eq!(untouched.used_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_update_keeps_the_used_count() {
+        let service = service();
+        let mut used = coupon("a", "shop-1", "SAVE", "10");
+        used.used_count = 3;
+        service.coupons.insert(&used).await.unwrap();
+
+        let updated = service
+            .clone()
+            .update_coupon(context::current(), coupon("a", "shop-1", "SAVE", "15"))
+            .await
+            .unwrap();
+        assert_eq!(updated.used_count, 3);
+        let stored = service.coupons.get("a").await.unwrap().unwrap();
+        assert_eq!(stored.used_count, 3);
+    }
+
+    #[tokio::test]
+    async fn test_batch_fails_once_the_template_is_used_up_in_the_shop() {
+        let service = service();
+        let template = CodeTemplate::new("", 7).with_alphabet("AB");
+        let every_code =
+            (0..128u32).map(|n| format!("{n:07b}").replace('0', "A").replace('1', "B"));
+        let existing: Vec<Coupon> = every_code
+            .map(|code| coupon(&code, "shop-1", &code, "10"))
+            .collect();
+        service.coupons.insert_many(&existing).await.unwrap();
+
+        let result = service
+            .clone()
+            .generate_coupon_batch(
+                context::current(),
+                "spring".to_string(),
+                template,
+                1,
+                coupon("", "shop-1", "", "10"),
+            )
+            .await;
+        assert!(matches!(result, Err(ServiceError::Conflict(_))));
+    }
+
+    #[tokio::test]
+    async fn test_legacy_free_form_code_is_found_as_typed() {
+        let service = service();
+        service
+            .coupons
+            .insert(&coupon("legacy", "shop-1", "summer-sale", "10"))
+            .await
+            .unwrap();
+
+        let found = service
+            .clone()
+            .get_coupon_by_code(context::current(), " summer-sale ".to_string())
+            .await
+            .unwrap();
+        assert_eq!(found.id, "legacy");
+        let total = service
+            .clone()
+            .apply_coupon(
+                context::current(),
+                "summer-sale".to_string(),
+                "shop-1".to_string(),
+                None,
+                usd("50"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(total.value(), dec("45"));
+    }
+
+    #[tokio::test]
+    async fn test_update_keeps_a_legacy_code_it_does_not_change() {
+        let service = service();
+        service
+            .coupons
+            .insert(&coupon("legacy", "shop-1", "summer-sale", "10"))
+            .await
+            .unwrap();
+
+        let mut update = coupon("legacy", "shop-1", "summer-sale", "15");
+        let updated = service
+            .clone()
+            .update_coupon(context::current(), update.clone())
+            .await
+            .unwrap();
+        assert_eq!(updated.code, "summer-sale");
+
+        update.code = "autumn-sale".to_string();
+        let updated = service
+            .clone()
+            .update_coupon(context::current(), update)
+            .await
+            .unwrap();
+        assert_eq!(updated.code, "AUTUMNSALE");
+    }
+}
@@ -0,0 +1,574 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use mongodb::Database;
+use tarpc::context::Context;
+use uuid::Uuid;
+
+use crate::{
+    decimal,
+    discount::{DiscountRule, DiscountService},
+    error::{Entity, ServiceError},
+    money::Money,
+    pricing,
+    redemption::RedemptionKey,
+    schedule::ActivationWindow,
+    storage::{
+        DiscountRuleRepository, RedemptionRepository, StorageError,
+        mongo::{MongoDiscountRuleRepository, MongoRedemptionRepository},
+    },
+};
+
+/// [`DiscountService`] over any [`DiscountRuleRepository`], with per-customer
+/// limits kept in a [`RedemptionRepository`].
+pub struct DiscountServiceImpl<R, L> {
+    rules: Arc<R>,
+    redemptions: Arc<L>,
+}
+
+impl<R, L> Clone for DiscountServiceImpl<R, L> {
+    fn clone(&self) -> Self {
+        Self {
+            rules: Arc::clone(&self.rules),
+            redemptions: Arc::clone(&self.redemptions),
+        }
+    }
+}
+
+impl<R: DiscountRuleRepository, L: RedemptionRepository> DiscountServiceImpl<R, L> {
+    pub fn new(rules: R, redemptions: L) -> Self {
+        Self {
+            rules: Arc::new(rules),
+            redemptions: Arc::new(redemptions),
+        }
+    }
+
+    async fn find_rule(&self, id: &str) -> Result<DiscountRule, ServiceError> {
+        self.rules
+            .get(id)
+            .await?
+            .ok_or_else(|| ServiceError::not_found(Entity::DiscountRule, id))
+    }
+
+    /// Fails with the reason a rule cannot be used at `now`, reading its
+    /// recurrence in `timezone`.
+    fn ensure_live(
+        rule: &DiscountRule,
+        now: DateTime<Utc>,
+        timezone: Tz,
+    ) -> Result<(), ServiceError> {
+        let key = rule.id.clone();
+        if !rule.is_active {
+            Err(ServiceError::Inactive {
+                entity: Entity::DiscountRule,
+                key,
+            })
+        } else if rule.start_date.is_some_and(|start| now < start) {
+            Err(ServiceError::NotYetActive {
+                entity: Entity::DiscountRule,
+                key,
+            })
+        } else if rule.end_date.is_some_and(|end| now > end) {
+            Err(ServiceError::Expired {
+                entity: Entity::DiscountRule,
+                key,
+            })
+        } else if !rule.is_scheduled_at(now, timezone) {
+            Err(ServiceError::not_applicable(
+                Entity::DiscountRule,
+                key,
+                "outside its recurring schedule",
+            ))
+        } else if !rule.has_uses_left() {
+            Err(ServiceError::UsageLimitReached {
+                entity: Entity::DiscountRule,
+                key,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Why [`DiscountRuleRepository::record_use`] refused the rule `id`.
+    async fn use_refused(&self, id: &str) -> ServiceError {
+        let key = id.to_string();
+        match self.rules.get(id).await {
+            Ok(None) => ServiceError::not_found(Entity::DiscountRule, key),
+            Ok(Some(rule)) if !rule.is_active => ServiceError::Inactive {
+                entity: Entity::DiscountRule,
+                key,
+            },
+            Ok(Some(_)) => ServiceError::UsageLimitReached {
+                entity: Entity::DiscountRule,
+                key,
+            },
+            Err(e) => e.into(),
+        }
+    }
+
+    /// Takes one use of rule `id`: first from the customer's ledger entry,
+    /// then from the rule's own count. If the second step fails the first is
+    /// given back.
+    async fn redeem_one(
+        &self,
+        id: &str,
+        customer_id: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> Result<DiscountRule, ServiceError> {
+        let rule = self.find_rule(id).await?;
+        let ledger_key = match customer_id {
+            Some(customer_id) => Some(RedemptionKey::new(
+                &rule.shop_id,
+                customer_id,
+                Entity::DiscountRule,
+                id,
+            )),
+            None if rule.max_usage_per_customer.is_some() => {
+                return Err(ServiceError::invalid_field(
+                    "customer_id",
+                    "required for rules limited per customer",
+                ));
+            }
+            None => None,
+        };
+        if let Some(key) = &ledger_key
+            && self
+                .redemptions
+                .record(key, rule.max_usage_per_customer, now)
+                .await?
+                .is_none()
+        {
+            return Err(ServiceError::UsageLimitReached {
+                entity: Entity::DiscountRule,
+                key: format!("{id} for customer {}", key.customer_id),
+            });
+        }
+        let refused = match self.rules.record_use(id, now).await {
+            Ok(Some(rule)) => return Ok(rule),
+            Ok(None) => self.use_refused(id).await,
+            Err(e) => e.into(),
+        };
+        if let Some(key) = &ledger_key {
+            let _ = self.redemptions.release(key, now).await;
+        }
+        Err(refused)
+    }
+
+    /// Gives back one use of `rule`, and the customer's ledger entry if
+    /// there is one.
+    async fn release_one(
+        &self,
+        id: &str,
+        customer_id: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> Result<DiscountRule, ServiceError> {
+        let rule = self
+            .rules
+            .release_use(id, now)
+            .await?
+            .ok_or_else(|| ServiceError::not_found(Entity::DiscountRule, id))?;
+        if let Some(customer_id) = customer_id {
+            let key = RedemptionKey::new(&rule.shop_id, customer_id, Entity::DiscountRule, id);
+            self.redemptions.release(&key, now).await?;
+        }
+        Ok(rule)
+    }
+}
+
+impl DiscountServiceImpl<MongoDiscountRuleRepository, MongoRedemptionRepository> {
+    /// Serves rules from the `discount_rules` collection in `db`, with the
+    /// ledger in `redemptions`.
+    pub async fn mongo(db: &Database) -> mongodb::error::Result<Self> {
+        Ok(Self::new(
+            MongoDiscountRuleRepository::new(db).await?,
+            MongoRedemptionRepository::new(db).await?,
+        ))
+    }
+}
+
+fn parse_timezone(timezone: &str) -> Result<Tz, ServiceError> {
+    timezone
+        .parse()
+        .map_err(|_| ServiceError::invalid_field("timezone", "unknown time zone"))
+}
+
+fn write_error(rule: &DiscountRule, error: StorageError) -> ServiceError {
+    match error {
+        StorageError::Duplicate(_) => ServiceError::Conflict(format!(
+            "discount rule {} or its code already exists in shop {}",
+            rule.id, rule.shop_id
+        )),
+        e => e.into(),
+    }
+}
+
+impl<R: DiscountRuleRepository, L: RedemptionRepository> DiscountService
+    for DiscountServiceImpl<R, L>
+{
+    async fn create_discount_rule(
+        self,
+        _: Context,
+        mut rule: DiscountRule,
+    ) -> Result<DiscountRule, ServiceError> {
+        if rule.id.is_empty() {
+            rule.id = Uuid::new_v4().to_string();
+        }
+        rule.validate()?;
+        let now = Utc::now();
+        rule.created_at = now;
+        rule.updated_at = now;
+        self.rules
+            .insert(&rule)
+            .await
+            .map_err(|e| write_error(&rule, e))?;
+        Ok(rule)
+    }
+
+    async fn get_discount_rule(self, _: Context, id: String) -> Result<DiscountRule, ServiceError> {
+        self.find_rule(&id).await
+    }
+
+    async fn update_discount_rule(
+        self,
+        _: Context,
+        mut rule: DiscountRule,
+    ) -> Result<DiscountRule, ServiceError> {
+        rule.validate()?;
+        let existing = self.find_rule(&rule.id).await?;
+        rule.created_at = existing.created_at;
+        // Uses are only counted through redemption, never by an update.
+        rule.usage_count = existing.usage_count;
+        rule.updated_at = Utc::now();
+        if !self
+            .rules
+            .replace(&rule)
+            .await
+            .map_err(|e| write_error(&rule, e))?
+        {
+            return Err(ServiceError::not_found(Entity::DiscountRule, rule.id));
+        }
+        Ok(rule)
+    }
+
+    async fn delete_discount_rule(self, _: Context, id: String) -> Result<(), ServiceError> {
+        if !self.rules.delete(&id).await? {
+            return Err(ServiceError::not_found(Entity::DiscountRule, id));
+        }
+        Ok(())
+    }
+
+    async fn list_discount_rules(
+        self,
+        _: Context,
+        shop_id: String,
+    ) -> Result<Vec<DiscountRule>, ServiceError> {
+        Ok(self.rules.list_by_shop(&shop_id).await?)
+    }
+
+    async fn apply_discount_rule(
+        self,
+        _: Context,
+        rule_id: String,
+        timezone: String,
+        cart_total: Money,
+    ) -> Result<Money, ServiceError> {
+        decimal::from_bson(cart_total.amount)
+            .map_err(|e| ServiceError::invalid_decimal("cart_total", e))?;
+        let timezone = parse_timezone(&timezone)?;
+        let rule = self.find_rule(&rule_id).await?;
+        Self::ensure_live(&rule, Utc::now(), timezone)?;
+        rule.check_currency(cart_total.currency)?;
+        Ok(pricing::apply_to_total(&rule, &cart_total))
+    }
+
+    async fn validate_discount_rule(
+        self,
+        _: Context,
+        rule_id: String,
+        timezone: String,
+    ) -> Result<bool, ServiceError> {
+        let timezone = parse_timezone(&timezone)?;
+        let rule = self.find_rule(&rule_id).await?;
+        Ok(rule.is_scheduled_at(Utc::now(), timezone) && rule.has_uses_left())
+    }
+
+    async fn redeem_discount_rules(
+        self,
+        _: Context,
+        customer_id: Option<String>,
+        rule_ids: Vec<String>,
+    ) -> Result<Vec<DiscountRule>, ServiceError> {
+        let now = Utc::now();
+        let customer_id = customer_id.as_deref();
+        let mut redeemed: Vec<DiscountRule> = Vec::with_capacity(rule_ids.len());
+        for id in &rule_ids {
+            match self.redeem_one(id, customer_id, now).await {
+                Ok(rule) => redeemed.push(rule),
+                Err(e) => {
+                    // Best effort: the redemption already failed, so errors
+                    // while giving back earlier uses are dropped.
+                    for rule in &redeemed {
+                        let _ = self.release_one(&rule.id, customer_id, now).await;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(redeemed)
+    }
+
+    async fn release_discount_rules(
+        self,
+        _: Context,
+        customer_id: Option<String>,
+        rule_ids: Vec<String>,
+    ) -> Result<Vec<DiscountRule>, ServiceError> {
+        let now = Utc::now();
+        let mut released = Vec::with_capacity(rule_ids.len());
+        for id in &rule_ids {
+            released.push(self.release_one(id, customer_id.as_deref(), now).await?);
+        }
+        Ok(released)
+    }
+
+    async fn list_activation_windows(
+        self,
+        _: Context,
+        rule_id: String,
+        timezone: String,
+        count: u32,
+    ) -> Result<Vec<ActivationWindow>, ServiceError> {
+        let timezone = parse_timezone(&timezone)?;
+        let rule = self.find_rule(&rule_id).await?;
+        Ok(rule.next_activation_windows(Utc::now(), timezone, count as usize))
+    }
+
+    async fn get_discount_rule_by_code(
+        self,
+        _: Context,
+        rule_code: String,
+    ) -> Result<DiscountRule, ServiceError> {
+        self.rules
+            .find_by_code(&rule_code, None)
+            .await?
+            .ok_or_else(|| ServiceError::not_found(Entity::DiscountRule, rule_code))
+    }
+
+    async fn get_discount_rule_by_code_and_shop(
+        self,
+        _: Context,
+        rule_code: String,
+        shop_id: String,
+    ) -> Result<DiscountRule, ServiceError> {
+        self.rules
+            .find_by_code(&rule_code, Some(&shop_id))
+            .await?
+            .ok_or_else(|| ServiceError::not_found(Entity::DiscountRule, rule_code))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use tarpc::context;
+
+    use super::*;
+    use crate::{
+        discount::DiscountAction,
+        fixtures::{d128, dec, rule, usd},
+        schedule::{Frequency, Recurrence},
+        storage::memory::{InMemoryDiscountRuleRepository, InMemoryRedemptionRepository},
+    };
+
+    type Service =
+        DiscountServiceImpl<InMemoryDiscountRuleRepository, InMemoryRedemptionRepository>;
+
+    fn service() -> Service {
+        DiscountServiceImpl::new(
+            InMemoryDiscountRuleRepository::new(),
+            InMemoryRedemptionRepository::new(),
+        )
+    }
+
+    async fn redeem(
+        service: &Service,
+        customer_id: &str,
+        rule_ids: &[&str],
+    ) -> Result<Vec<DiscountRule>, ServiceError> {
+        service
+            .clone()
+            .redeem_discount_rules(
+                context::current(),
+                Some(customer_id.to_string()),
+                rule_ids.iter().map(|id| id.to_string()).collect(),
+            )
+            .await
+    }
+
+    async fn ledger_count(service: &Service, customer_id: &str, rule_id: &str) -> i32 {
+        let entries = service
+            .redemptions
+            .list_for_customer("shop-1", customer_id)
+            .await
+            .unwrap();
+        entries
+            .iter()
+            .find(|entry| entry.key.entity_id == rule_id)
+            .map_or(0, |entry| entry.count)
+    }
+
+    #[tokio::test]
+    async fn test_only_live_rules_apply_to_a_total() {
+        let service = service();
+        let ten_percent = vec![DiscountAction::PercentageOff {
+            percent: d128("10"),
+        }];
+        service
+            .rules
+            .insert(&rule("live", ten_percent.clone()))
+            .await
+            .unwrap();
+        let mut paused = rule("paused", ten_percent);
+        paused.is_active = false;
+        service.rules.insert(&paused).await.unwrap();
+        let apply = |id: &str| {
+            service.clone().apply_discount_rule(
+                context::current(),
+                id.to_string(),
+                "UTC".to_string(),
+                usd("100"),
+            )
+        };
+
+        let total = apply("live").await.unwrap();
+        assert_eq!(total.value(), dec("90"));
+        assert_eq!(
+            apply("paused").await,
+            Err(ServiceError::Inactive {
+                entity: Entity::DiscountRule,
+                key: "paused".to_string(),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_recurring_rule_is_refused_outside_its_windows() {
+        let service = service();
+        let mut later = rule(
+            "later",
+            vec![DiscountAction::PercentageOff {
+                percent: d128("10"),
+            }],
+        );
+        later.recurrence = Some(Recurrence {
+            frequency: Frequency::Yearly,
+            interval: 1,
+            starts_on: NaiveDate::from_ymd_opt(2999, 1, 1).unwrap(),
+            by_month: Vec::new(),
+            by_month_day: Vec::new(),
+            by_weekday: Vec::new(),
+            start_hour: 0,
+            start_minute: 0,
+            duration_minutes: 24 * 60,
+            exclusions: Vec::new(),
+            blackouts: Vec::new(),
+        });
+        service.rules.insert(&later).await.unwrap();
+        let apply = |timezone: &str| {
+            service.clone().apply_discount_rule(
+                context::current(),
+                "later".to_string(),
+                timezone.to_string(),
+                usd("100"),
+            )
+        };
+
+        assert!(matches!(
+            apply("Europe/Paris").await,
+            Err(ServiceError::NotApplicable { .. })
+        ));
+        assert!(matches!(
+            apply("Nowhere/Special").await,
+            Err(ServiceError::ValidationFailed { .. })
+        ));
+        let valid = service
+            .clone()
+            .validate_discount_rule(
+                context::current(),
+                "later".to_string(),
+                "Europe/Paris".to_string(),
+            )
+            .await
+            .unwrap();
+        assert!(!valid);
+    }
+
+    #[tokio::test]
+    async fn test_failed_redemption_gives_back_earlier_rules() {
+        let service = service();
+        let mut limited = rule("limited", Vec::new());
+        limited.max_usage_per_customer = Some(3);
+        service.rules.insert(&limited).await.unwrap();
+        let mut used_up = rule("used-up", Vec::new());
+        used_up.max_usage = Some(1);
+        used_up.usage_count = 1;
+        service.rules.insert(&used_up).await.unwrap();
+
+        let result = redeem(&service, "customer-1", &["limited", "used-up"]).await;
+        assert!(matches!(
+            result,
+            Err(ServiceError::UsageLimitReached { .. })
+        ));
+        let limited = service.rules.get("limited").await.unwrap().unwrap();
+        assert_eq!(limited.usage_count, 0);
+        assert_eq!(ledger_count(&service, "customer-1", "limited").await, 0);
+        assert_eq!(ledger_count(&service, "customer-1", "used-up").await, 0);
+
+        let redeemed = redeem(&service, "customer-1", &["limited"]).await.unwrap();
+        assert_eq!(redeemed[0].usage_count, 1);
+        assert_eq!(ledger_count(&service, "customer-1", "limited").await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_customer_is_required_for_rules_limited_per_customer() {
+        let service = service();
+        let mut limited = rule("limited", Vec::new());
+        limited.max_usage_per_customer = Some(1);
+        service.rules.insert(&limited).await.unwrap();
+
+        let result = service
+            .clone()
+            .redeem_discount_rules(context::current(), None, vec!["limited".to_string()])
+            .await;
+        assert!(matches!(result, Err(ServiceError::ValidationFailed { .. })));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_redemptions_respect_the_per_customer_limit() {
+        let service = service();
+        let mut once = rule("once", Vec::new());
+        once.max_usage_per_customer = Some(1);
+        service.rules.insert(&once).await.unwrap();
+
+        let attempts: Vec<_> = (0..20)
+            .map(|_| {
+                let service = service.clone();
+                tokio::spawn(async move { redeem(&service, "customer-1", &["once"]).await })
+            })
+            .collect();
+        let mut redeemed = 0;
+        for attempt in attempts {
+            match attempt.await.unwrap() {
+                Ok(_) => redeemed += 1,
+                Err(e) => assert!(matches!(e, ServiceError::UsageLimitReached { .. })),
+            }
+        }
+        assert_eq!(redeemed, 1);
+        let once = service.rules.get("once").await.unwrap().unwrap();
+        assert_eq!(once.usage_count, 1);
+        assert_eq!(ledger_count(&service, "customer-1", "once").await, 1);
+
+        redeem(&service, "customer-2", &["once"]).await.unwrap();
+    }
+}
@@ -0,0 +1,302 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use mongodb::Database;
+use tarpc::context::Context;
+use uuid::Uuid;
+
+use crate::{
+    decimal,
+    error::{Entity, ServiceError},
+    membership::{Membership, MembershipService, MembershipTier},
+    money::Money,
+    storage::{
+        MembershipFilter, MembershipRepository, StorageError, mongo::MongoMembershipRepository,
+    },
+};
+
+/// [`MembershipService`] over any [`MembershipRepository`]. A customer holds
+/// at most one membership per shop.
+pub struct MembershipServiceImpl<R> {
+    memberships: Arc<R>,
+}
+
+impl<R> Clone for MembershipServiceImpl<R> {
+    fn clone(&self) -> Self {
+        Self {
+            memberships: Arc::clone(&self.memberships),
+        }
+    }
+}
+
+impl<R: MembershipRepository> MembershipServiceImpl<R> {
+    pub fn new(memberships: R) -> Self {
+        Self {
+            memberships: Arc::new(memberships),
+        }
+    }
+
+    async fn find_membership(
+        &self,
+        filter: MembershipFilter,
+        key: impl Into<String>,
+    ) -> Result<Membership, ServiceError> {
+        self.memberships
+            .find_one(&filter)
+            .await?
+            .ok_or_else(|| ServiceError::not_found(Entity::Membership, key))
+    }
+
+    async fn find_by_id(&self, id: &str) -> Result<Membership, ServiceError> {
+        let filter = MembershipFilter {
+            id: Some(id.to_string()),
+            ..Default::default()
+        };
+        self.find_membership(filter, id).await
+    }
+}
+
+impl MembershipServiceImpl<MongoMembershipRepository> {
+    /// Serves memberships from the `memberships` collection in `db`.
+    pub async fn mongo(db: &Database) -> mongodb::error::Result<Self> {
+        Ok(Self::new(MongoMembershipRepository::new(db).await?))
+    }
+}
+
+fn write_error(membership: &Membership, error: StorageError) -> ServiceError {
+    match error {
+        StorageError::Duplicate(_) => ServiceError::Conflict(format!(
+            "customer {} already has a membership in shop {}",
+            membership.customer_id, membership.shop_id
+        )),
+        e => e.into(),
+    }
+}
+
+impl<R: MembershipRepository> MembershipService for MembershipServiceImpl<R> {
+    async fn create_membership(
+        self,
+        _: Context,
+        mut membership: Membership,
+    ) -> Result<Membership, ServiceError> {
+        if membership.id.is_empty() {
+            membership.id = Uuid::new_v4().to_string();
+        }
+        membership.validate()?;
+        let now = Utc::now();
+        membership.created_at = now;
+        membership.updated_at = now;
+        self.memberships
+            .insert(&membership)
+            .await
+            .map_err(|e| write_error(&membership, e))?;
+        Ok(membership)
+    }
+
+    async fn get_membership(self, _: Context, id: String) -> Result<Membership, ServiceError> {
+        self.find_by_id(&id).await
+    }
+
+    async fn update_membership(
+        self,
+        _: Context,
+        mut membership: Membership,
+    ) -> Result<Membership, ServiceError> {
+        membership.validate()?;
+        let existing = self.find_by_id(&membership.id).await?;
+        membership.created_at = existing.created_at;
+        membership.updated_at = Utc::now();
+        if !self
+            .memberships
+            .replace(&membership)
+            .await
+            .map_err(|e| write_error(&membership, e))?
+        {
+            return Err(ServiceError::not_found(Entity::Membership, membership.id));
+        }
+        Ok(membership)
+    }
+
+    async fn delete_membership(self, _: Context, id: String) -> Result<(), ServiceError> {
+        if !self.memberships.delete(&id).await? {
+            return Err(ServiceError::not_found(Entity::Membership, id));
+        }
+        Ok(())
+    }
+
+    async fn list_memberships(
+        self,
+        _: Context,
+        shop_id: String,
+    ) -> Result<Vec<Membership>, ServiceError> {
+        Ok(self.memberships.list_by_shop(&shop_id).await?)
+    }
+
+    async fn apply_membership_discount(
+        self,
+        _: Context,
+        membership_id: String,
+        cart_total: Money,
+    ) -> Result<Money, ServiceError> {
+        decimal::from_bson(cart_total.amount)
+            .map_err(|e| ServiceError::invalid_decimal("cart_total", e))?;
+        let membership = self.find_by_id(&membership_id).await?;
+        let now = Utc::now();
+        let entity = Entity::Membership;
+        let key = membership_id;
+        if !membership.is_active {
+            return Err(ServiceError::Inactive { entity, key });
+        }
+        if membership.starts_at.is_some_and(|start| now < start) {
+            return Err(ServiceError::NotYetActive { entity, key });
+        }
+        if membership.expires_at.is_some_and(|end| now > end) {
+            return Err(ServiceError::Expired { entity, key });
+        }
+        Ok(membership.apply_to_total(&cart_total))
+    }
+
+    async fn validate_membership(
+        self,
+        _: Context,
+        membership_id: String,
+    ) -> Result<bool, ServiceError> {
+        let membership = self.find_by_id(&membership_id).await?;
+        Ok(membership.is_valid_at(Utc::now()))
+    }
+
+    async fn get_membership_by_customer_id(
+        self,
+        _: Context,
+        customer_id: String,
+    ) -> Result<Membership, ServiceError> {
+        let filter = MembershipFilter {
+            customer_id: Some(customer_id.clone()),
+            ..Default::default()
+        };
+        self.find_membership(filter, customer_id).await
+    }
+
+    async fn get_membership_by_customer_id_and_shop(
+        self,
+        _: Context,
+        customer_id: String,
+        shop_id: String,
+    ) -> Result<Membership, ServiceError> {
+        let filter = MembershipFilter {
+            customer_id: Some(customer_id.clone()),
+            shop_id: Some(shop_id.clone()),
+            ..Default::default()
+        };
+        self.find_membership(filter, format!("{customer_id} in shop {shop_id}"))
+            .await
+    }
+
+    async fn get_membership_by_tier(
+        self,
+        _: Context,
+        tier: MembershipTier,
+    ) -> Result<Membership, ServiceError> {
+        let filter = MembershipFilter {
+            tier: Some(tier.clone()),
+            ..Default::default()
+        };
+        self.find_membership(filter, tier.to_string()).await
+    }
+
+    async fn get_membership_by_tier_and_shop(
+        self,
+        _: Context,
+        tier: MembershipTier,
+        shop_id: String,
+    ) -> Result<Membership, ServiceError> {
+        let filter = MembershipFilter {
+            tier: Some(tier.clone()),
+            shop_id: Some(shop_id.clone()),
+            ..Default::default()
+        };
+        self.find_membership(filter, format!("{tier} in shop {shop_id}"))
+            .await
+    }
+
+    async fn get_membership_by_tier_and_customer(
+        self,
+        _: Context,
+        tier: MembershipTier,
+        customer_id: String,
+    ) -> Result<Membership, ServiceError> {
+        let filter = MembershipFilter {
+            tier: Some(tier.clone()),
+            customer_id: Some(customer_id.clone()),
+            ..Default::default()
+        };
+        self.find_membership(filter, format!("{tier} for customer {customer_id}"))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tarpc::context;
+
+    use super::*;
+    use crate::{
+        fixtures::{dec, membership, usd},
+        money::Currency,
+        storage::memory::InMemoryMembershipRepository,
+    };
+
+    fn service() -> MembershipServiceImpl<InMemoryMembershipRepository> {
+        MembershipServiceImpl::new(InMemoryMembershipRepository::new())
+    }
+
+    #[tokio::test]
+    async fn test_second_membership_in_a_shop_is_a_conflict() {
+        let service = service();
+        let create = |id: &str, shop_id: &str| {
+            service.clone().create_membership(
+                context::current(),
+                membership(id, shop_id, "customer-1", "10"),
+            )
+        };
+        create("a", "shop-1").await.unwrap();
+        create("b", "shop-2").await.unwrap();
+        assert!(matches!(
+            create("c", "shop-1").await,
+            Err(ServiceError::Conflict(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_discount_needs_a_current_membership() {
+        let service = service();
+        let mut lapsed = membership("lapsed", "shop-1", "customer-1", "10");
+        lapsed.expires_at = Some(Utc::now() - chrono::Duration::days(1));
+        service.memberships.insert(&lapsed).await.unwrap();
+        service
+            .memberships
+            .insert(&membership("gold", "shop-2", "customer-1", "15"))
+            .await
+            .unwrap();
+        let apply = |id: &str, total: Money| {
+            service
+                .clone()
+                .apply_membership_discount(context::current(), id.to_string(), total)
+        };
+
+        assert!(matches!(
+            apply("lapsed", usd("100")).await,
+            Err(ServiceError::Expired { .. })
+        ));
+        assert!(matches!(
+            apply("missing", usd("100")).await,
+            Err(ServiceError::NotFound { .. })
+        ));
+        let dollars = apply("gold", usd("19.99")).await.unwrap();
+        assert_eq!(dollars.value(), dec("16.99"));
+        let yen = apply("gold", Money::new(dec("999"), Currency::JPY))
+            .await
+            .unwrap();
+        assert_eq!((yen.value(), yen.currency), (dec("849"), Currency::JPY));
+    }
+}
@@ -0,0 +1,275 @@
+use std::cmp::Reverse;
+
+use bson::Decimal128;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    discount::{DiscountRule, EvaluationContext},
+    money::Currency,
+    pricing,
+};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub enum StackingMode {
+    /// Apply every eligible rule, subject to exclusivity and stacking groups.
+    #[default]
+    Stack,
+    /// Apply only the eligible rule that saves the customer the most.
+    BestForCustomer,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StackingPolicy {
+    pub mode: StackingMode,
+    /// Upper bound on the combined discount, as a percentage of the cart
+    /// total. Shipping waivers are not counted against it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_combined_percentage: Option<Decimal128>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum SuppressionReason {
+    ConditionsNotMet,
+    /// A higher priority exclusive rule was applied and stopped processing.
+    ExcludedBy {
+        rule_id: String,
+    },
+    /// Another rule from the same stacking group was already applied.
+    StackingGroupTaken {
+        group: String,
+        rule_id: String,
+    },
+    /// Best-for-customer mode picked a rule that saves more.
+    NotBestDiscount {
+        best_rule_id: String,
+    },
+    /// The combined discount cap was used up before this rule was reached.
+    CombinedCapReached,
+    /// The rule's amounts are in `currency`, not the cart's.
+    CurrencyMismatch {
+        currency: Currency,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SuppressedRule {
+    pub rule_id: String,
+    pub reason: SuppressionReason,
+}
+
+/// Decides which of `rules` apply to `ctx` and in what order.
+///
+/// Eligible rules are walked highest priority first. An exclusive rule is
+/// applied and stops everything after it, and at most one rule per stacking
+/// group is applied. In best-for-customer mode each eligible rule is priced
+/// on its own and only the largest saving (shipping included) survives; ties
+/// go to the higher priority rule. The combined cap is enforced later, while
+/// the selected rules are priced.
+pub(crate) fn select<'a>(
+    ctx: &EvaluationContext,
+    rules: &'a [DiscountRule],
+    policy: &StackingPolicy,
+) -> (Vec<&'a DiscountRule>, Vec<SuppressedRule>) {
+    let mut suppressed = Vec::new();
+    let mut eligible = Vec::new();
+    for rule in rules {
+        if let Some(currency) = rule.foreign_currency(ctx.cart.currency) {
+            suppressed.push(SuppressedRule {
+                rule_id: rule.id.clone(),
+                reason: SuppressionReason::CurrencyMismatch { currency },
+            });
+        } else if rule.evaluate(ctx) {
+            eligible.push(rule);
+        } else {
+            suppressed.push(SuppressedRule {
+                rule_id: rule.id.clone(),
+                reason: SuppressionReason::ConditionsNotMet,
+            });
+        }
+    }
+    eligible.sort_by_key(|rule| Reverse(rule.priority));
+
+    if policy.mode == StackingMode::BestForCustomer {
+        let mut best: Option<(&DiscountRule, _)> = None;
+        for rule in &eligible {
+            let saving = pricing::standalone_saving(ctx, rule, policy);
+            if best.is_none_or(|(_, best_saving)| saving > best_saving) {
+                best = Some((rule, saving));
+            }
+        }
+        let Some((best, _)) = best else {
+            return (Vec::new(), suppressed);
+        };
+        for rule in eligible.iter().filter(|r| r.id != best.id) {
+            suppressed.push(SuppressedRule {
+                rule_id: rule.id.clone(),
+                reason: SuppressionReason::NotBestDiscount {
+                    best_rule_id: best.id.clone(),
+                },
+            });
+        }
+        return (vec![best], suppressed);
+    }
+
+    let mut selected: Vec<&DiscountRule> = Vec::new();
+    let mut stopped_by: Option<&DiscountRule> = None;
+    for rule in eligible {
+        if let Some(exclusive) = stopped_by {
+            suppressed.push(SuppressedRule {
+                rule_id: rule.id.clone(),
+                reason: SuppressionReason::ExcludedBy {
+                    rule_id: exclusive.id.clone(),
+                },
+            });
+            continue;
+        }
+        if let Some(group) = &rule.stacking_group
+            && let Some(taken) = selected
+                .iter()
+                .find(|r| r.stacking_group.as_ref() == Some(group))
+        {
+            suppressed.push(SuppressedRule {
+                rule_id: rule.id.clone(),
+                reason: SuppressionReason::StackingGroupTaken {
+                    group: group.clone(),
+                    rule_id: taken.id.clone(),
+                },
+            });
+            continue;
+        }
+        if rule.is_exclusive {
+            stopped_by = Some(rule);
+        }
+        selected.push(rule);
+    }
+    (selected, suppressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        discount::{Condition, DiscountAction},
+        fixtures::{cart, ctx, d128, line, rule, usd},
+        money::Money,
+    };
+
+    fn percent_off(id: &str, percent: &str, priority: i32) -> DiscountRule {
+        let mut rule = rule(
+            id,
+            vec![DiscountAction::PercentageOff {
+                percent: d128(percent),
+            }],
+        );
+        rule.priority = priority;
+        rule
+    }
+
+    fn ids(rules: &[&DiscountRule]) -> Vec<String> {
+        rules.iter().map(|r| r.id.clone()).collect()
+    }
+
+    fn context() -> EvaluationContext {
+        ctx(cart(vec![line("a", "a", "100", 1)]))
+    }
+
+    #[test]
+    fn test_exclusive_rule_stops_lower_priorities() {
+        let mut exclusive = percent_off("exclusive", "10", 2);
+        exclusive.is_exclusive = true;
+        let rules = [
+            percent_off("low", "5", 1),
+            exclusive,
+            percent_off("high", "5", 3),
+        ];
+
+        let (selected, suppressed) = select(&context(), &rules, &StackingPolicy::default());
+        assert_eq!(ids(&selected), vec!["high", "exclusive"]);
+        assert_eq!(
+            suppressed,
+            vec![SuppressedRule {
+                rule_id: "low".to_string(),
+                reason: SuppressionReason::ExcludedBy {
+                    rule_id: "exclusive".to_string(),
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn test_one_rule_per_stacking_group() {
+        let mut first = percent_off("first", "10", 2);
+        let mut second = percent_off("second", "20", 1);
+        first.stacking_group = Some("seasonal".to_string());
+        second.stacking_group = Some("seasonal".to_string());
+        let rules = [second, first, percent_off("other", "5", 0)];
+
+        let (selected, suppressed) = select(&context(), &rules, &StackingPolicy::default());
+        assert_eq!(ids(&selected), vec!["first", "other"]);
+        assert_eq!(
+            suppressed,
+            vec![SuppressedRule {
+                rule_id: "second".to_string(),
+                reason: SuppressionReason::StackingGroupTaken {
+                    group: "seasonal".to_string(),
+                    rule_id: "first".to_string(),
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn test_best_for_customer_keeps_the_largest_saving() {
+        let shipping = rule("shipping", vec![DiscountAction::FreeShipping]);
+        let rules = [
+            percent_off("small", "3", 2),
+            shipping,
+            percent_off("large", "5", 1),
+        ];
+        let policy = StackingPolicy {
+            mode: StackingMode::BestForCustomer,
+            ..Default::default()
+        };
+
+        let (selected, suppressed) = select(&context(), &rules, &policy);
+        // Free shipping saves the 5.00 shipping cost, tying with 5% of 100;
+        // the tie goes to the higher priority rule.
+        assert_eq!(ids(&selected), vec!["large"]);
+        assert_eq!(suppressed.len(), 2);
+        assert!(suppressed.iter().all(|s| s.reason
+            == SuppressionReason::NotBestDiscount {
+                best_rule_id: "large".to_string(),
+            }));
+    }
+
+    #[test]
+    fn test_ineligible_and_foreign_rules_are_suppressed_with_a_reason() {
+        let mut unmet = percent_off("unmet", "10", 0);
+        unmet.conditions = vec![Condition::MinimumSpend { amount: usd("500") }];
+        let yen = rule(
+            "yen",
+            vec![DiscountAction::FixedAmountOff {
+                amount: Money::new(100.into(), Currency::JPY),
+            }],
+        );
+
+        let rules = [unmet, yen];
+        let (selected, suppressed) = select(&context(), &rules, &StackingPolicy::default());
+        assert!(selected.is_empty());
+        assert_eq!(
+            suppressed,
+            vec![
+                SuppressedRule {
+                    rule_id: "unmet".to_string(),
+                    reason: SuppressionReason::ConditionsNotMet,
+                },
+                SuppressedRule {
+                    rule_id: "yen".to_string(),
+                    reason: SuppressionReason::CurrencyMismatch {
+                        currency: Currency::JPY,
+                    },
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,162 @@
+pub mod memory;
+pub mod mongo;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+
+use crate::{
+    coupon::Coupon,
+    discount::DiscountRule,
+    membership::{Membership, MembershipTier},
+    redemption::{Redemption, RedemptionKey},
+};
+
+#[derive(Debug, Clone, Error)]
+pub enum StorageError {
+    /// A unique constraint (id, code within a shop, customer within a shop)
+    /// would be violated.
+    #[error("duplicate key: {0}")]
+    Duplicate(String),
+    #[error("storage backend error: {0}")]
+    Backend(String),
+}
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+/// Persistence for [`DiscountRule`]s. Ids are unique, and so are codes
+/// within a shop.
+#[async_trait]
+pub trait DiscountRuleRepository: Send + Sync + 'static {
+    async fn insert(&self, rule: &DiscountRule) -> StorageResult<()>;
+    async fn get(&self, id: &str) -> StorageResult<Option<DiscountRule>>;
+    /// Replaces the rule with the same id; returns false if there is none.
+    async fn replace(&self, rule: &DiscountRule) -> StorageResult<bool>;
+    /// Returns false if there was nothing to delete.
+    async fn delete(&self, id: &str) -> StorageResult<bool>;
+    /// Rules of a shop, highest priority first.
+    async fn list_by_shop(&self, shop_id: &str) -> StorageResult<Vec<DiscountRule>>;
+    async fn find_by_code(
+        &self,
+        code: &str,
+        shop_id: Option<&str>,
+    ) -> StorageResult<Option<DiscountRule>>;
+    /// Atomically counts one use of the rule if it is active and below its
+    /// `max_usage`, returning the updated rule, or `None` when it is missing,
+    /// inactive or used up.
+    async fn record_use(&self, id: &str, now: DateTime<Utc>)
+    -> StorageResult<Option<DiscountRule>>;
+    /// Gives back one use recorded by [`Self::record_use`]; the count never
+    /// drops below zero. Returns `None` when the rule is missing.
+    async fn release_use(
+        &self,
+        id: &str,
+        now: DateTime<Utc>,
+    ) -> StorageResult<Option<DiscountRule>>;
+}
+
+/// Persistence for [`Coupon`]s. Ids are unique, and so are codes within a
+/// shop.
+#[async_trait]
+pub trait CouponRepository: Send + Sync + 'static {
+    async fn insert(&self, coupon: &Coupon) -> StorageResult<()>;
+    /// Inserts all of `coupons`, or none of them if any would be a duplicate.
+    async fn insert_many(&self, coupons: &[Coupon]) -> StorageResult<()>;
+    async fn get(&self, id: &str) -> StorageResult<Option<Coupon>>;
+    /// Replaces the coupon with the same id; returns false if there is none.
+    async fn replace(&self, coupon: &Coupon) -> StorageResult<bool>;
+    /// Returns false if there was nothing to delete.
+    async fn delete(&self, id: &str) -> StorageResult<bool>;
+    /// Coupons of a shop, newest first.
+    async fn list_by_shop(&self, shop_id: &str) -> StorageResult<Vec<Coupon>>;
+    async fn list_by_campaign(
+        &self,
+        shop_id: &str,
+        campaign_id: &str,
+    ) -> StorageResult<Vec<Coupon>>;
+    async fn find_by_code(
+        &self,
+        code: &str,
+        shop_id: Option<&str>,
+    ) -> StorageResult<Option<Coupon>>;
+    /// Which of `codes` are already taken in the shop.
+    async fn existing_codes(&self, shop_id: &str, codes: &[String]) -> StorageResult<Vec<String>>;
+    /// Atomically takes one use of coupon `id` if
+    /// [`Coupon::is_redeemable_at`] holds at `now`, returning the updated
+    /// coupon, or `None` when it is missing or not redeemable. Coupons are
+    /// redeemed by id because codes are only unique within a shop.
+    async fn redeem(&self, id: &str, now: DateTime<Utc>) -> StorageResult<Option<Coupon>>;
+    /// Gives back one use taken by [`Self::redeem`]; the count never drops
+    /// below zero. Returns `None` when there is no coupon `id`.
+    async fn release(&self, id: &str, now: DateTime<Utc>) -> StorageResult<Option<Coupon>>;
+}
+
+/// Narrows a membership lookup; unset fields match anything.
+#[derive(Debug, Clone, Default)]
+pub struct MembershipFilter {
+    pub id: Option<String>,
+    pub shop_id: Option<String>,
+    pub customer_id: Option<String>,
+    pub tier: Option<MembershipTier>,
+}
+
+impl MembershipFilter {
+    /// Tiers match on name and target; descriptions are ignored.
+    pub fn matches(&self, membership: &Membership) -> bool {
+        self.id.as_ref().is_none_or(|id| *id == membership.id)
+            && self
+                .shop_id
+                .as_ref()
+                .is_none_or(|shop_id| *shop_id == membership.shop_id)
+            && self
+                .customer_id
+                .as_ref()
+                .is_none_or(|customer_id| *customer_id == membership.customer_id)
+            && self.tier.as_ref().is_none_or(|tier| {
+                tier.name == membership.tier.name && tier.target == membership.tier.target
+            })
+    }
+}
+
+/// Persistence for [`Membership`]s. Ids are unique, and a customer holds at
+/// most one membership per shop.
+#[async_trait]
+pub trait MembershipRepository: Send + Sync + 'static {
+    async fn insert(&self, membership: &Membership) -> StorageResult<()>;
+    /// Replaces the membership with the same id; returns false if there is
+    /// none.
+    async fn replace(&self, membership: &Membership) -> StorageResult<bool>;
+    /// Returns false if there was nothing to delete.
+    async fn delete(&self, id: &str) -> StorageResult<bool>;
+    /// Memberships of a shop, newest first.
+    async fn list_by_shop(&self, shop_id: &str) -> StorageResult<Vec<Membership>>;
+    /// The most recently updated membership matching `filter`.
+    async fn find_one(&self, filter: &MembershipFilter) -> StorageResult<Option<Membership>>;
+}
+
+/// The per-customer redemption ledger. There is one entry per
+/// [`RedemptionKey`], created on first use.
+#[async_trait]
+pub trait RedemptionRepository: Send + Sync + 'static {
+    /// A customer's entries in a shop, for building an evaluation context.
+    async fn list_for_customer(
+        &self,
+        shop_id: &str,
+        customer_id: &str,
+    ) -> StorageResult<Vec<Redemption>>;
+    /// Atomically counts one more use under `key` if it stays within `limit`,
+    /// returning the updated entry, or `None` when the limit is reached.
+    async fn record(
+        &self,
+        key: &RedemptionKey,
+        limit: Option<i32>,
+        now: DateTime<Utc>,
+    ) -> StorageResult<Option<Redemption>>;
+    /// Gives back one use recorded by [`Self::record`]; the count never drops
+    /// below zero. Returns `None` when there is no entry for `key`.
+    async fn release(
+        &self,
+        key: &RedemptionKey,
+        now: DateTime<Utc>,
+    ) -> StorageResult<Option<Redemption>>;
+}
@@ -0,0 +1,668 @@
+use std::{
+    cmp::Reverse,
+    collections::HashSet,
+    sync::{Mutex, MutexGuard},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::{
+    coupon::Coupon,
+    discount::DiscountRule,
+    membership::Membership,
+    redemption::{Redemption, RedemptionKey},
+    storage::{
+        CouponRepository, DiscountRuleRepository, MembershipFilter, MembershipRepository,
+        RedemptionRepository, StorageError, StorageResult,
+    },
+};
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// [`DiscountRuleRepository`] kept in process memory, with the same
+/// uniqueness rules and ordering as the MongoDB one.
+#[derive(Default)]
+pub struct InMemoryDiscountRuleRepository {
+    rules: Mutex<Vec<DiscountRule>>,
+}
+
+impl InMemoryDiscountRuleRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn check_unique(rules: &[DiscountRule], rule: &DiscountRule) -> StorageResult<()> {
+        let clash = rules
+            .iter()
+            .filter(|r| r.id != rule.id)
+            .any(|r| r.shop_id == rule.shop_id && r.code.is_some() && r.code == rule.code);
+        if clash {
+            return Err(StorageError::Duplicate(format!(
+                "code {:?} in shop {}",
+                rule.code, rule.shop_id
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DiscountRuleRepository for InMemoryDiscountRuleRepository {
+    async fn insert(&self, rule: &DiscountRule) -> StorageResult<()> {
+        let mut rules = lock(&self.rules);
+        if rules.iter().any(|r| r.id == rule.id) {
+            return Err(StorageError::Duplicate(format!("id {}", rule.id)));
+        }
+        Self::check_unique(&rules, rule)?;
+        rules.push(rule.clone());
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> StorageResult<Option<DiscountRule>> {
+        Ok(lock(&self.rules).iter().find(|r| r.id == id).cloned())
+    }
+
+    async fn replace(&self, rule: &DiscountRule) -> StorageResult<bool> {
+        let mut rules = lock(&self.rules);
+        Self::check_unique(&rules, rule)?;
+        match rules.iter_mut().find(|r| r.id == rule.id) {
+            Some(existing) => {
+                *existing = rule.clone();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn delete(&self, id: &str) -> StorageResult<bool> {
+        let mut rules = lock(&self.rules);
+        let before = rules.len();
+        rules.retain(|r| r.id != id);
+        Ok(rules.len() < before)
+    }
+
+    async fn list_by_shop(&self, shop_id: &str) -> StorageResult<Vec<DiscountRule>> {
+        let mut rules: Vec<_> = lock(&self.rules)
+            .iter()
+            .filter(|r| r.shop_id == shop_id)
+            .cloned()
+            .collect();
+        rules.sort_by_key(|r| Reverse(r.priority));
+        Ok(rules)
+    }
+
+    async fn find_by_code(
+        &self,
+        code: &str,
+        shop_id: Option<&str>,
+    ) -> StorageResult<Option<DiscountRule>> {
+        Ok(lock(&self.rules)
+            .iter()
+            .find(|r| r.code.as_deref() == Some(code) && shop_id.is_none_or(|s| s == r.shop_id))
+            .cloned())
+    }
+
+    async fn record_use(
+        &self,
+        id: &str,
+        now: DateTime<Utc>,
+    ) -> StorageResult<Option<DiscountRule>> {
+        let mut rules = lock(&self.rules);
+        let Some(rule) = rules
+            .iter_mut()
+            .find(|r| r.id == id && r.is_active && r.has_uses_left())
+        else {
+            return Ok(None);
+        };
+        rule.usage_count += 1;
+        rule.updated_at = now;
+        Ok(Some(rule.clone()))
+    }
+
+    async fn release_use(
+        &self,
+        id: &str,
+        now: DateTime<Utc>,
+    ) -> StorageResult<Option<DiscountRule>> {
+        let mut rules = lock(&self.rules);
+        let Some(rule) = rules.iter_mut().find(|r| r.id == id) else {
+            return Ok(None);
+        };
+        if rule.usage_count > 0 {
+            rule.usage_count -= 1;
+            rule.updated_at = now;
+        }
+        Ok(Some(rule.clone()))
+    }
+}
+
+/// [`CouponRepository`] kept in process memory. Redemption checks and
+/// increments under one lock, matching the MongoDB conditional update.
+#[derive(Default)]
+pub struct InMemoryCouponRepository {
+    coupons: Mutex<Vec<Coupon>>,
+}
+
+impl InMemoryCouponRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn check_unique(coupons: &[Coupon], coupon: &Coupon) -> StorageResult<()> {
+        let clash = coupons
+            .iter()
+            .filter(|c| c.id != coupon.id)
+            .any(|c| c.shop_id == coupon.shop_id && c.code == coupon.code);
+        if clash {
+            return Err(StorageError::Duplicate(format!(
+                "code {} in shop {}",
+                coupon.code, coupon.shop_id
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CouponRepository for InMemoryCouponRepository {
+    async fn insert(&self, coupon: &Coupon) -> StorageResult<()> {
+        let mut coupons = lock(&self.coupons);
+        if coupons.iter().any(|c| c.id == coupon.id) {
+            return Err(StorageError::Duplicate(format!("id {}", coupon.id)));
+        }
+        Self::check_unique(&coupons, coupon)?;
+        coupons.push(coupon.clone());
+        Ok(())
+    }
+
+    async fn insert_many(&self, batch: &[Coupon]) -> StorageResult<()> {
+        let mut coupons = lock(&self.coupons);
+        let before = coupons.len();
+        for coupon in batch {
+            let checked = if coupons.iter().any(|c| c.id == coupon.id) {
+                Err(StorageError::Duplicate(format!("id {}", coupon.id)))
+            } else {
+                Self::check_unique(&coupons, coupon)
+            };
+            if let Err(e) = checked {
+                coupons.truncate(before);
+                return Err(e);
+            }
+            coupons.push(coupon.clone());
+        }
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> StorageResult<Option<Coupon>> {
+        Ok(lock(&self.coupons).iter().find(|c| c.id == id).cloned())
+    }
+
+    async fn replace(&self, coupon: &Coupon) -> StorageResult<bool> {
+        let mut coupons = lock(&self.coupons);
+        Self::check_unique(&coupons, coupon)?;
+        match coupons.iter_mut().find(|c| c.id == coupon.id) {
+            Some(existing) => {
+                *existing = coupon.clone();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn delete(&self, id: &str) -> StorageResult<bool> {
+        let mut coupons = lock(&self.coupons);
+        let before = coupons.len();
+        coupons.retain(|c| c.id != id);
+        Ok(coupons.len() < before)
+    }
+
+    async fn list_by_shop(&self, shop_id: &str) -> StorageResult<Vec<Coupon>> {
+        let mut coupons: Vec<_> = lock(&self.coupons)
+            .iter()
+            .filter(|c| c.shop_id == shop_id)
+            .cloned()
+            .collect();
+        coupons.sort_by_key(|c| Reverse(c.created_at));
+        Ok(coupons)
+    }
+
+    async fn list_by_campaign(
+        &self,
+        shop_id: &str,
+        campaign_id: &str,
+    ) -> StorageResult<Vec<Coupon>> {
+        let mut coupons: Vec<_> = lock(&self.coupons)
+            .iter()
+            .filter(|c| c.shop_id == shop_id && c.campaign_id.as_deref() == Some(campaign_id))
+            .cloned()
+            .collect();
+        coupons.sort_by(|a, b| a.code.cmp(&b.code));
+        Ok(coupons)
+    }
+
+    async fn existing_codes(&self, shop_id: &str, codes: &[String]) -> StorageResult<Vec<String>> {
+        let codes: HashSet<&str> = codes.iter().map(String::as_str).collect();
+        Ok(lock(&self.coupons)
+            .iter()
+            .filter(|c| c.shop_id == shop_id && codes.contains(c.code.as_str()))
+            .map(|c| c.code.clone())
+            .collect())
+    }
+
+    async fn find_by_code(
+        &self,
+        code: &str,
+        shop_id: Option<&str>,
+    ) -> StorageResult<Option<Coupon>> {
+        Ok(lock(&self.coupons)
+            .iter()
+            .find(|c| c.code == code && shop_id.is_none_or(|s| s == c.shop_id))
+            .cloned())
+    }
+
+    async fn redeem(&self, id: &str, now: DateTime<Utc>) -> StorageResult<Option<Coupon>> {
+        let mut coupons = lock(&self.coupons);
+        let Some(coupon) = coupons
+            .iter_mut()
+            .find(|c| c.id == id && c.is_redeemable_at(now))
+        else {
+            return Ok(None);
+        };
+        coupon.used_count += 1;
+        coupon.updated_at = now;
+        Ok(Some(coupon.clone()))
+    }
+
+    async fn release(&self, id: &str, now: DateTime<Utc>) -> StorageResult<Option<Coupon>> {
+        let mut coupons = lock(&self.coupons);
+        let Some(coupon) = coupons.iter_mut().find(|c| c.id == id) else {
+            return Ok(None);
+        };
+        if coupon.used_count > 0 {
+            coupon.used_count -= 1;
+            coupon.updated_at = now;
+        }
+        Ok(Some(coupon.clone()))
+    }
+}
+
+/// [`MembershipRepository`] kept in process memory, with the same
+/// uniqueness rules and ordering as the MongoDB one.
+#[derive(Default)]
+pub struct InMemoryMembershipRepository {
+    memberships: Mutex<Vec<Membership>>,
+}
+
+impl InMemoryMembershipRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn check_unique(memberships: &[Membership], membership: &Membership) -> StorageResult<()> {
+        let clash = memberships
+            .iter()
+            .filter(|m| m.id != membership.id)
+            .any(|m| m.shop_id == membership.shop_id && m.customer_id == membership.customer_id);
+        if clash {
+            return Err(StorageError::Duplicate(format!(
+                "customer {} in shop {}",
+                membership.customer_id, membership.shop_id
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MembershipRepository for InMemoryMembershipRepository {
+    async fn insert(&self, membership: &Membership) -> StorageResult<()> {
+        let mut memberships = lock(&self.memberships);
+        if memberships.iter().any(|m| m.id == membership.id) {
+            return Err(StorageError::Duplicate(format!("id {}", membership.id)));
+        }
+        Self::check_unique(&memberships, membership)?;
+        memberships.push(membership.clone());
+        Ok(())
+    }
+
+    async fn replace(&self, membership: &Membership) -> StorageResult<bool> {
+        let mut memberships = lock(&self.memberships);
+        Self::check_unique(&memberships, membership)?;
+        match memberships.iter_mut().find(|m| m.id == membership.id) {
+            Some(existing) => {
+                *existing = membership.clone();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn delete(&self, id: &str) -> StorageResult<bool> {
+        let mut memberships = lock(&self.memberships);
+        let before = memberships.len();
+        memberships.retain(|m| m.id != id);
+        Ok(memberships.len() < before)
+    }
+
+    async fn list_by_shop(&self, shop_id: &str) -> StorageResult<Vec<Membership>> {
+        let mut memberships: Vec<_> = lock(&self.memberships)
+            .iter()
+            .filter(|m| m.shop_id == shop_id)
+            .cloned()
+            .collect();
+        memberships.sort_by_key(|m| Reverse(m.created_at));
+        Ok(memberships)
+    }
+
+    async fn find_one(&self, filter: &MembershipFilter) -> StorageResult<Option<Membership>> {
+        Ok(lock(&self.memberships)
+            .iter()
+            .filter(|m| filter.matches(m))
+            .max_by_key(|m| m.updated_at)
+            .cloned())
+    }
+}
+
+/// [`RedemptionRepository`] kept in process memory. Limit checks and
+/// increments happen under one lock, matching the MongoDB upsert.
+#[derive(Default)]
+pub struct InMemoryRedemptionRepository {
+    redemptions: Mutex<Vec<Redemption>>,
+}
+
+impl InMemoryRedemptionRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl RedemptionRepository for InMemoryRedemptionRepository {
+    async fn list_for_customer(
+        &self,
+        shop_id: &str,
+        customer_id: &str,
+    ) -> StorageResult<Vec<Redemption>> {
+        Ok(lock(&self.redemptions)
+            .iter()
+            .filter(|r| r.key.shop_id == shop_id && r.key.customer_id == customer_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn record(
+        &self,
+        key: &RedemptionKey,
+        limit: Option<i32>,
+        now: DateTime<Utc>,
+    ) -> StorageResult<Option<Redemption>> {
+        let mut redemptions = lock(&self.redemptions);
+        let index = match redemptions.iter().position(|r| r.key == *key) {
+            Some(index) => index,
+            None => {
+                redemptions.push(Redemption {
+                    key: key.clone(),
+                    count: 0,
+                    updated_at: now,
+                });
+                redemptions.len() - 1
+            }
+        };
+        let redemption = &mut redemptions[index];
+        if limit.is_some_and(|limit| redemption.count >= limit) {
+            if redemption.count == 0 {
+                redemptions.remove(index);
+            }
+            return Ok(None);
+        }
+        redemption.count += 1;
+        redemption.updated_at = now;
+        Ok(Some(redemption.clone()))
+    }
+
+    async fn release(
+        &self,
+        key: &RedemptionKey,
+        now: DateTime<Utc>,
+    ) -> StorageResult<Option<Redemption>> {
+        let mut redemptions = lock(&self.redemptions);
+        let Some(redemption) = redemptions.iter_mut().find(|r| r.key == *key) else {
+            return Ok(None);
+        };
+        if redemption.count > 0 {
+            redemption.count -= 1;
+            redemption.updated_at = now;
+        }
+        Ok(Some(redemption.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::Entity,
+        fixtures::{coupon, membership, now, rule},
+        membership::{MembershipTarget, MembershipTier},
+    };
+
+    #[tokio::test]
+    async fn test_codes_are_unique_within_a_shop_only() {
+        let repo = InMemoryCouponRepository::new();
+        repo.insert(&coupon("a", "shop-1", "SAVE", "10"))
+            .await
+            .unwrap();
+        repo.insert(&coupon("b", "shop-2", "SAVE", "10"))
+            .await
+            .unwrap();
+        assert!(matches!(
+            repo.insert(&coupon("c", "shop-1", "SAVE", "10")).await,
+            Err(StorageError::Duplicate(_))
+        ));
+        assert!(matches!(
+            repo.replace(&coupon("b", "shop-1", "SAVE", "10")).await,
+            Err(StorageError::Duplicate(_))
+        ));
+        let found = repo.find_by_code("SAVE", Some("shop-2")).await.unwrap();
+        assert_eq!(found.map(|c| c.id), Some("b".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_redeem_stops_at_max_uses() {
+        let repo = InMemoryCouponRepository::new();
+        let mut limited = coupon("a", "shop-1", "ONCE", "10");
+        limited.max_uses = Some(1);
+        repo.insert(&limited).await.unwrap();
+
+        let redeemed = repo.redeem("a", now()).await.unwrap().unwrap();
+        assert_eq!(redeemed.used_count, 1);
+        assert!(repo.redeem("a", now()).await.unwrap().is_none());
+        assert!(repo.redeem("missing", now()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_coupons_are_redeemed_and_released_by_id_not_code() {
+        let repo = InMemoryCouponRepository::new();
+        let mut used_up = coupon("a", "shop-a", "SAVE", "10");
+        used_up.max_uses = Some(1);
+        used_up.used_count = 1;
+        repo.insert(&used_up).await.unwrap();
+        repo.insert(&coupon("b", "shop-b", "SAVE", "10"))
+            .await
+            .unwrap();
+
+        assert!(repo.redeem("a", now()).await.unwrap().is_none());
+        assert_eq!(repo.get("b").await.unwrap().unwrap().used_count, 0);
+
+        let released = repo.release("a", now()).await.unwrap().unwrap();
+        assert_eq!(released.id, "a");
+        assert_eq!(released.used_count, 0);
+        assert_eq!(repo.get("b").await.unwrap().unwrap().used_count, 0);
+    }
+
+    fn key(customer_id: &str) -> RedemptionKey {
+        RedemptionKey::new("shop-1", customer_id, Entity::Coupon, "coupon-1")
+    }
+
+    #[tokio::test]
+    async fn test_ledger_counts_up_to_the_limit_and_back() {
+        let ledger = InMemoryRedemptionRepository::new();
+        assert_eq!(
+            ledger
+                .record(&key("c"), Some(2), now())
+                .await
+                .unwrap()
+                .unwrap()
+                .count,
+            1
+        );
+        assert_eq!(
+            ledger
+                .record(&key("c"), Some(2), now())
+                .await
+                .unwrap()
+                .unwrap()
+                .count,
+            2
+        );
+        assert!(
+            ledger
+                .record(&key("c"), Some(2), now())
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        assert_eq!(
+            ledger
+                .release(&key("c"), now())
+                .await
+                .unwrap()
+                .unwrap()
+                .count,
+            1
+        );
+        assert_eq!(
+            ledger
+                .release(&key("c"), now())
+                .await
+                .unwrap()
+                .unwrap()
+                .count,
+            0
+        );
+        assert_eq!(
+            ledger
+                .release(&key("c"), now())
+                .await
+                .unwrap()
+                .unwrap()
+                .count,
+            0
+        );
+        assert!(
+            ledger
+                .release(&key("other"), now())
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_refused_first_use_leaves_no_ledger_entry() {
+        let ledger = InMemoryRedemptionRepository::new();
+        assert!(
+            ledger
+                .record(&key("c"), Some(0), now())
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            ledger
+                .list_for_customer("shop-1", "c")
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rule_uses_stop_at_max_usage() {
+        let repo = InMemoryDiscountRuleRepository::new();
+        let mut limited = rule("rule-1", Vec::new());
+        limited.max_usage = Some(1);
+        repo.insert(&limited).await.unwrap();
+
+        assert_eq!(
+            repo.record_use("rule-1", now())
+                .await
+                .unwrap()
+                .unwrap()
+                .usage_count,
+            1
+        );
+        assert!(repo.record_use("rule-1", now()).await.unwrap().is_none());
+        assert!(repo.record_use("missing", now()).await.unwrap().is_none());
+        assert_eq!(
+            repo.release_use("rule-1", now())
+                .await
+                .unwrap()
+                .unwrap()
+                .usage_count,
+            0
+        );
+        assert_eq!(
+            repo.release_use("rule-1", now())
+                .await
+                .unwrap()
+                .unwrap()
+                .usage_count,
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batch_insert_is_all_or_nothing() {
+        let repo = InMemoryCouponRepository::new();
+        repo.insert(&coupon("taken", "shop-1", "B", "10"))
+            .await
+            .unwrap();
+        let batch = [
+            coupon("a", "shop-1", "A", "10"),
+            coupon("b", "shop-1", "B", "10"),
+        ];
+        assert!(repo.insert_many(&batch).await.is_err());
+        assert_eq!(repo.list_by_shop("shop-1").await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_one_membership_per_customer_and_shop() {
+        let repo = InMemoryMembershipRepository::new();
+        repo.insert(&membership("a", "shop-1", "customer-1", "10"))
+            .await
+            .unwrap();
+        repo.insert(&membership("b", "shop-2", "customer-1", "10"))
+            .await
+            .unwrap();
+        assert!(
+            repo.insert(&membership("c", "shop-1", "customer-1", "10"))
+                .await
+                .is_err()
+        );
+
+        let filter = MembershipFilter {
+            shop_id: Some("shop-2".to_string()),
+            tier: Some(MembershipTier::new("Gold", MembershipTarget::Customer)),
+            ..Default::default()
+        };
+        let found = repo.find_one(&filter).await.unwrap();
+        assert_eq!(found.map(|m| m.id), Some("b".to_string()));
+    }
+}
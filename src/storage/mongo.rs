@@ -0,0 +1,536 @@
+use async_trait::async_trait;
+use bson::{Document, doc};
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use mongodb::{
+    Collection, Database, IndexModel,
+    error::{Error, ErrorKind, WriteFailure},
+    options::{IndexOptions, ReturnDocument},
+};
+
+use crate::{
+    coupon::Coupon,
+    discount::DiscountRule,
+    membership::Membership,
+    redemption::{Redemption, RedemptionKey},
+    storage::{
+        CouponRepository, DiscountRuleRepository, MembershipFilter, MembershipRepository,
+        RedemptionRepository, StorageError, StorageResult,
+    },
+};
+
+pub const DISCOUNT_RULES_COLLECTION: &str = "discount_rules";
+pub const COUPONS_COLLECTION: &str = "coupons";
+pub const MEMBERSHIPS_COLLECTION: &str = "memberships";
+pub const REDEMPTIONS_COLLECTION: &str = "redemptions";
+
+const DUPLICATE_KEY: i32 = 11000;
+
+/// Most values sent in one `$in` query.
+const IN_QUERY_CHUNK: usize = 1000;
+
+/// Whether `error` is a unique index violation, however the driver wrapped it.
+fn is_duplicate_key(error: &Error) -> bool {
+    match error.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(e)) => e.code == DUPLICATE_KEY,
+        ErrorKind::InsertMany(e) => e
+            .write_errors
+            .iter()
+            .flatten()
+            .any(|e| e.code == DUPLICATE_KEY),
+        ErrorKind::Command(e) => e.code == DUPLICATE_KEY,
+        _ => false,
+    }
+}
+
+impl From<Error> for StorageError {
+    fn from(error: Error) -> Self {
+        if is_duplicate_key(&error) {
+            StorageError::Duplicate(error.to_string())
+        } else {
+            StorageError::Backend(error.to_string())
+        }
+    }
+}
+
+fn unique(keys: Document) -> IndexModel {
+    IndexModel::builder()
+        .keys(keys)
+        .options(IndexOptions::builder().unique(true).build())
+        .build()
+}
+
+fn index(keys: Document) -> IndexModel {
+    IndexModel::builder().keys(keys).build()
+}
+
+fn code_filter(code: &str, shop_id: Option<&str>) -> Document {
+    let mut filter = doc! { "code": code };
+    if let Some(shop_id) = shop_id {
+        filter.insert("shop_id", shop_id);
+    }
+    filter
+}
+
+#[derive(Clone)]
+pub struct MongoDiscountRuleRepository {
+    rules: Collection<DiscountRule>,
+}
+
+impl MongoDiscountRuleRepository {
+    /// Opens the rule collection in `db` and makes sure its indexes exist.
+    pub async fn new(db: &Database) -> mongodb::error::Result<Self> {
+        let rules = db.collection::<DiscountRule>(DISCOUNT_RULES_COLLECTION);
+        rules
+            .create_indexes([
+                unique(doc! { "id": 1 }),
+                index(doc! { "shop_id": 1, "priority": -1 }),
+                IndexModel::builder()
+                    .keys(doc! { "shop_id": 1, "code": 1 })
+                    .options(
+                        IndexOptions::builder()
+                            .unique(true)
+                            .partial_filter_expression(doc! { "code": { "$exists": true } })
+                            .build(),
+                    )
+                    .build(),
+                index(doc! { "code": 1 }),
+            ])
+            .await?;
+        Ok(Self { rules })
+    }
+}
+
+#[async_trait]
+impl DiscountRuleRepository for MongoDiscountRuleRepository {
+    async fn insert(&self, rule: &DiscountRule) -> StorageResult<()> {
+        self.rules.insert_one(rule).await?;
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> StorageResult<Option<DiscountRule>> {
+        Ok(self.rules.find_one(doc! { "id": id }).await?)
+    }
+
+    async fn replace(&self, rule: &DiscountRule) -> StorageResult<bool> {
+        let result = self
+            .rules
+            .replace_one(doc! { "id": &rule.id }, rule)
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    async fn delete(&self, id: &str) -> StorageResult<bool> {
+        let result = self.rules.delete_one(doc! { "id": id }).await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    async fn list_by_shop(&self, shop_id: &str) -> StorageResult<Vec<DiscountRule>> {
+        Ok(self
+            .rules
+            .find(doc! { "shop_id": shop_id })
+            .sort(doc! { "priority": -1 })
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn find_by_code(
+        &self,
+        code: &str,
+        shop_id: Option<&str>,
+    ) -> StorageResult<Option<DiscountRule>> {
+        Ok(self.rules.find_one(code_filter(code, shop_id)).await?)
+    }
+
+    /// The cap check and the increment are one conditional update, so a
+    /// rule limited to N uses is never used N + 1 times.
+    async fn record_use(
+        &self,
+        id: &str,
+        now: DateTime<Utc>,
+    ) -> StorageResult<Option<DiscountRule>> {
+        let now = bson::DateTime::from_chrono(now);
+        Ok(self
+            .rules
+            .find_one_and_update(
+                doc! {
+                    "id": id,
+                    "is_active": true,
+                    "$or": [
+                        { "max_usage": null },
+                        { "$expr": { "$lt": ["$usage_count", "$max_usage"] } },
+                    ],
+                },
+                doc! {
+                    "$inc": { "usage_count": 1 },
+                    "$set": { "updated_at": now },
+                },
+            )
+            .return_document(ReturnDocument::After)
+            .await?)
+    }
+
+    async fn release_use(
+        &self,
+        id: &str,
+        now: DateTime<Utc>,
+    ) -> StorageResult<Option<DiscountRule>> {
+        let now = bson::DateTime::from_chrono(now);
+        let released = self
+            .rules
+            .find_one_and_update(
+                doc! { "id": id, "usage_count": { "$gt": 0 } },
+                doc! {
+                    "$inc": { "usage_count": -1 },
+                    "$set": { "updated_at": now },
+                },
+            )
+            .return_document(ReturnDocument::After)
+            .await?;
+        match released {
+            Some(rule) => Ok(Some(rule)),
+            None => self.get(id).await,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MongoCouponRepository {
+    coupons: Collection<Coupon>,
+}
+
+impl MongoCouponRepository {
+    /// Opens the coupon collection in `db` and makes sure its indexes exist.
+    pub async fn new(db: &Database) -> mongodb::error::Result<Self> {
+        let coupons = db.collection::<Coupon>(COUPONS_COLLECTION);
+        coupons
+            .create_indexes([
+                unique(doc! { "id": 1 }),
+                unique(doc! { "shop_id": 1, "code": 1 }),
+                index(doc! { "code": 1 }),
+                index(doc! { "shop_id": 1, "campaign_id": 1 }),
+            ])
+            .await?;
+        Ok(Self { coupons })
+    }
+}
+
+#[async_trait]
+impl CouponRepository for MongoCouponRepository {
+    async fn insert(&self, coupon: &Coupon) -> StorageResult<()> {
+        self.coupons.insert_one(coupon).await?;
+        Ok(())
+    }
+
+    /// Without a transaction an ordered insert keeps what it wrote before a
+    /// duplicate, so those coupons are deleted again before reporting it.
+    async fn insert_many(&self, coupons: &[Coupon]) -> StorageResult<()> {
+        if coupons.is_empty() {
+            return Ok(());
+        }
+        let Err(error) = self.coupons.insert_many(coupons).await else {
+            return Ok(());
+        };
+        for chunk in coupons.chunks(IN_QUERY_CHUNK) {
+            let ids: Vec<&str> = chunk.iter().map(|c| c.id.as_str()).collect();
+            self.coupons
+                .delete_many(doc! { "id": { "$in": ids } })
+                .await?;
+        }
+        Err(error.into())
+    }
+
+    async fn get(&self, id: &str) -> StorageResult<Option<Coupon>> {
+        Ok(self.coupons.find_one(doc! { "id": id }).await?)
+    }
+
+    async fn replace(&self, coupon: &Coupon) -> StorageResult<bool> {
+        let result = self
+            .coupons
+            .replace_one(doc! { "id": &coupon.id }, coupon)
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    async fn delete(&self, id: &str) -> StorageResult<bool> {
+        let result = self.coupons.delete_one(doc! { "id": id }).await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    async fn list_by_shop(&self, shop_id: &str) -> StorageResult<Vec<Coupon>> {
+        Ok(self
+            .coupons
+            .find(doc! { "shop_id": shop_id })
+            .sort(doc! { "created_at": -1 })
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn list_by_campaign(
+        &self,
+        shop_id: &str,
+        campaign_id: &str,
+    ) -> StorageResult<Vec<Coupon>> {
+        Ok(self
+            .coupons
+            .find(doc! { "shop_id": shop_id, "campaign_id": campaign_id })
+            .sort(doc! { "code": 1 })
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn existing_codes(&self, shop_id: &str, codes: &[String]) -> StorageResult<Vec<String>> {
+        let mut existing = Vec::new();
+        for chunk in codes.chunks(IN_QUERY_CHUNK) {
+            let found: Vec<Coupon> = self
+                .coupons
+                .find(doc! { "shop_id": shop_id, "code": { "$in": chunk } })
+                .await?
+                .try_collect()
+                .await?;
+            existing.extend(found.into_iter().map(|c| c.code));
+        }
+        Ok(existing)
+    }
+
+    async fn find_by_code(
+        &self,
+        code: &str,
+        shop_id: Option<&str>,
+    ) -> StorageResult<Option<Coupon>> {
+        Ok(self.coupons.find_one(code_filter(code, shop_id)).await?)
+    }
+
+    /// The redeemability checks and the increment happen in a single
+    /// conditional update, so concurrent checkouts cannot both take the last
+    /// use.
+    async fn redeem(&self, id: &str, now: DateTime<Utc>) -> StorageResult<Option<Coupon>> {
+        let now = bson::DateTime::from_chrono(now);
+        Ok(self
+            .coupons
+            .find_one_and_update(
+                doc! {
+                    "id": id,
+                    "is_active": true,
+                    "$and": [
+                        { "$or": [{ "starts_at": null }, { "starts_at": { "$lte": now } }] },
+                        { "$or": [{ "expires_at": null }, { "expires_at": { "$gte": now } }] },
+                        { "$or": [
+                            { "max_uses": null },
+                            { "$expr": { "$lt": ["$used_count", "$max_uses"] } },
+                        ] },
+                        { "$or": [{ "is_single_use": false }, { "used_count": 0 }] },
+                    ],
+                },
+                doc! {
+                    "$inc": { "used_count": 1 },
+                    "$set": { "updated_at": now },
+                },
+            )
+            .return_document(ReturnDocument::After)
+            .await?)
+    }
+
+    async fn release(&self, id: &str, now: DateTime<Utc>) -> StorageResult<Option<Coupon>> {
+        let now = bson::DateTime::from_chrono(now);
+        let released = self
+            .coupons
+            .find_one_and_update(
+                doc! { "id": id, "used_count": { "$gt": 0 } },
+                doc! {
+                    "$inc": { "used_count": -1 },
+                    "$set": { "updated_at": now },
+                },
+            )
+            .return_document(ReturnDocument::After)
+            .await?;
+        match released {
+            Some(coupon) => Ok(Some(coupon)),
+            None => self.get(id).await,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MongoMembershipRepository {
+    memberships: Collection<Membership>,
+}
+
+impl MongoMembershipRepository {
+    /// Opens the membership collection in `db` and makes sure its indexes
+    /// exist.
+    pub async fn new(db: &Database) -> mongodb::error::Result<Self> {
+        let memberships = db.collection::<Membership>(MEMBERSHIPS_COLLECTION);
+        memberships
+            .create_indexes([
+                unique(doc! { "id": 1 }),
+                unique(doc! { "shop_id": 1, "customer_id": 1 }),
+                index(doc! { "customer_id": 1 }),
+                index(doc! { "tier.name": 1, "tier.target": 1, "shop_id": 1 }),
+            ])
+            .await?;
+        Ok(Self { memberships })
+    }
+}
+
+fn membership_query(filter: &MembershipFilter) -> StorageResult<Document> {
+    let mut query = Document::new();
+    if let Some(id) = &filter.id {
+        query.insert("id", id);
+    }
+    if let Some(shop_id) = &filter.shop_id {
+        query.insert("shop_id", shop_id);
+    }
+    if let Some(customer_id) = &filter.customer_id {
+        query.insert("customer_id", customer_id);
+    }
+    if let Some(tier) = &filter.tier {
+        let target =
+            bson::to_bson(&tier.target).map_err(|e| StorageError::Backend(e.to_string()))?;
+        query.insert("tier.name", &tier.name);
+        query.insert("tier.target", target);
+    }
+    Ok(query)
+}
+
+#[async_trait]
+impl MembershipRepository for MongoMembershipRepository {
+    async fn insert(&self, membership: &Membership) -> StorageResult<()> {
+        self.memberships.insert_one(membership).await?;
+        Ok(())
+    }
+
+    async fn replace(&self, membership: &Membership) -> StorageResult<bool> {
+        let result = self
+            .memberships
+            .replace_one(doc! { "id": &membership.id }, membership)
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    async fn delete(&self, id: &str) -> StorageResult<bool> {
+        let result = self.memberships.delete_one(doc! { "id": id }).await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    async fn list_by_shop(&self, shop_id: &str) -> StorageResult<Vec<Membership>> {
+        Ok(self
+            .memberships
+            .find(doc! { "shop_id": shop_id })
+            .sort(doc! { "created_at": -1 })
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn find_one(&self, filter: &MembershipFilter) -> StorageResult<Option<Membership>> {
+        Ok(self
+            .memberships
+            .find_one(membership_query(filter)?)
+            .sort(doc! { "updated_at": -1 })
+            .await?)
+    }
+}
+
+#[derive(Clone)]
+pub struct MongoRedemptionRepository {
+    redemptions: Collection<Redemption>,
+}
+
+impl MongoRedemptionRepository {
+    /// Opens the redemption ledger in `db` and makes sure its indexes exist.
+    pub async fn new(db: &Database) -> mongodb::error::Result<Self> {
+        let redemptions = db.collection::<Redemption>(REDEMPTIONS_COLLECTION);
+        redemptions
+            .create_indexes([unique(
+                doc! { "shop_id": 1, "customer_id": 1, "entity": 1, "entity_id": 1 },
+            )])
+            .await?;
+        Ok(Self { redemptions })
+    }
+}
+
+fn key_filter(key: &RedemptionKey) -> StorageResult<Document> {
+    bson::to_document(key).map_err(|e| StorageError::Backend(e.to_string()))
+}
+
+#[async_trait]
+impl RedemptionRepository for MongoRedemptionRepository {
+    async fn list_for_customer(
+        &self,
+        shop_id: &str,
+        customer_id: &str,
+    ) -> StorageResult<Vec<Redemption>> {
+        Ok(self
+            .redemptions
+            .find(doc! { "shop_id": shop_id, "customer_id": customer_id })
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    /// An upsert whose filter carries the limit. When the entry is already
+    /// at the limit the filter misses, the upsert collides with the unique
+    /// index and the use is refused. A collision can also mean a concurrent
+    /// first use created the entry, so the update is tried once more.
+    async fn record(
+        &self,
+        key: &RedemptionKey,
+        limit: Option<i32>,
+        now: DateTime<Utc>,
+    ) -> StorageResult<Option<Redemption>> {
+        if limit.is_some_and(|limit| limit <= 0) {
+            return Ok(None);
+        }
+        let mut filter = key_filter(key)?;
+        if let Some(limit) = limit {
+            filter.insert("count", doc! { "$lt": limit });
+        }
+        let update = doc! {
+            "$inc": { "count": 1 },
+            "$set": { "updated_at": bson::DateTime::from_chrono(now) },
+        };
+        for _ in 0..2 {
+            let result = self
+                .redemptions
+                .find_one_and_update(filter.clone(), update.clone())
+                .upsert(true)
+                .return_document(ReturnDocument::After)
+                .await;
+            match result {
+                Ok(redemption) => return Ok(redemption),
+                Err(e) if is_duplicate_key(&e) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(None)
+    }
+
+    async fn release(
+        &self,
+        key: &RedemptionKey,
+        now: DateTime<Utc>,
+    ) -> StorageResult<Option<Redemption>> {
+        let filter = key_filter(key)?;
+        let mut decrement = filter.clone();
+        decrement.insert("count", doc! { "$gt": 0 });
+        let released = self
+            .redemptions
+            .find_one_and_update(
+                decrement,
+                doc! {
+                    "$inc": { "count": -1 },
+                    "$set": { "updated_at": bson::DateTime::from_chrono(now) },
+                },
+            )
+            .return_document(ReturnDocument::After)
+            .await?;
+        match released {
+            Some(redemption) => Ok(Some(redemption)),
+            None => Ok(self.redemptions.find_one(filter).await?),
+        }
+    }
+}
@@ -0,0 +1,411 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    datetime::datetime_serialization,
+    discount::{Condition, DiscountRule, EvaluationContext, bundle_count},
+    error::Entity,
+};
+
+/// Why a rule did or did not match a context, in a form support tooling can
+/// store and display.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleTrace {
+    pub rule_id: String,
+    pub rule_name: String,
+    pub passed: bool,
+    pub inactive: bool,
+    pub window: WindowTrace,
+    pub usage: UsageTrace,
+    pub conditions: Vec<ConditionTrace>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WindowTrace {
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub now: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, with = "datetime_serialization")]
+    pub start_date: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, with = "datetime_serialization")]
+    pub end_date: Option<DateTime<Utc>>,
+    pub not_started: bool,
+    pub ended: bool,
+    /// Inside the date window but not in any window of the rule's
+    /// recurrence.
+    #[serde(default)]
+    pub outside_recurrence: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageTrace {
+    pub usage_count: i32,
+    pub max_usage: Option<i32>,
+    /// Uses by the customer being evaluated; `None` when anonymous.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub customer_usage_count: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_usage_per_customer: Option<i32>,
+    pub exhausted: bool,
+}
+
+/// One condition's outcome. `observed` is what the context held and
+/// `expected` what the condition asked for, both rendered as text; nested
+/// conditions of `All`, `Any` and `Not` are reported in `children`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConditionTrace {
+    pub condition: String,
+    pub passed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub observed: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<ConditionTrace>,
+}
+
+impl DiscountRule {
+    /// Evaluates the rule like [`DiscountRule::evaluate`], but reports every
+    /// check instead of stopping at the first failure.
+    pub fn evaluate_with_trace(&self, ctx: &EvaluationContext) -> RuleTrace {
+        let window = WindowTrace {
+            now: ctx.now,
+            start_date: self.start_date,
+            end_date: self.end_date,
+            not_started: self.start_date.is_some_and(|start| ctx.now < start),
+            ended: self.end_date.is_some_and(|end| ctx.now > end),
+            outside_recurrence: self
+                .recurrence
+                .as_ref()
+                .is_some_and(|r| !r.is_active_at(ctx.now, ctx.timezone)),
+        };
+        let usage = UsageTrace {
+            usage_count: self.usage_count,
+            max_usage: self.max_usage,
+            customer_usage_count: ctx.redemptions_of(Entity::DiscountRule, &self.id),
+            max_usage_per_customer: self.max_usage_per_customer,
+            exhausted: !self.has_uses_left()
+                || !ctx.within_customer_limit(
+                    Entity::DiscountRule,
+                    &self.id,
+                    self.max_usage_per_customer,
+                ),
+        };
+        let conditions: Vec<ConditionTrace> =
+            self.conditions.iter().map(|c| c.trace(ctx)).collect();
+
+        RuleTrace {
+            rule_id: self.id.clone(),
+            rule_name: self.name.clone(),
+            passed: self.is_active
+                && !window.not_started
+                && !window.ended
+                && !window.outside_recurrence
+                && !usage.exhausted
+                && conditions.iter().all(|c| c.passed),
+            inactive: !self.is_active,
+            window,
+            usage,
+            conditions,
+        }
+    }
+}
+
+impl Condition {
+    pub fn trace(&self, ctx: &EvaluationContext) -> ConditionTrace {
+        let leaf = |name: &str, observed: String, expected: String| ConditionTrace {
+            condition: name.to_string(),
+            passed: self.evaluate(ctx),
+            observed: Some(observed),
+            expected: Some(expected),
+            children: Vec::new(),
+        };
+
+        match self {
+            Condition::CartTotal { operator, value } => leaf(
+                "CartTotal",
+                format!("cart_total {}", ctx.cart_total()),
+                format!("{operator:?} {value}"),
+            ),
+            Condition::ProductCategory { category_ids } => {
+                let mut in_cart: Vec<&str> = ctx
+                    .cart
+                    .lines
+                    .iter()
+                    .filter(|l| l.quantity > 0)
+                    .flat_map(|l| l.categories.iter().map(String::as_str))
+                    .collect();
+                in_cart.sort_unstable();
+                in_cart.dedup();
+                leaf(
+                    "ProductCategory",
+                    format!("categories in cart {in_cart:?}"),
+                    format!("any of {category_ids:?}"),
+                )
+            }
+            Condition::CustomerGroup { group_ids } => leaf(
+                "CustomerGroup",
+                format!("customer groups {:?}", ctx.customer_groups),
+                format!("any of {group_ids:?}"),
+            ),
+            Condition::PurchaseHistory {
+                min_orders,
+                timeframe_days,
+            } => leaf(
+                "PurchaseHistory",
+                format!(
+                    "{} orders {}",
+                    ctx.order_count_within(*timeframe_days),
+                    describe_timeframe(*timeframe_days)
+                ),
+                format!(
+                    "at least {min_orders} orders {}",
+                    describe_timeframe(*timeframe_days)
+                ),
+            ),
+            Condition::SpendHistory {
+                min_amount,
+                timeframe_days,
+            } => leaf(
+                "SpendHistory",
+                format!(
+                    "spent {} {}",
+                    ctx.spend_within(*timeframe_days, min_amount.currency),
+                    describe_timeframe(*timeframe_days)
+                ),
+                format!(
+                    "at least {min_amount} spent {}",
+                    describe_timeframe(*timeframe_days)
+                ),
+            ),
+            Condition::TimeOfDay {
+                start_hour,
+                start_minute,
+                end_hour,
+                end_minute,
+            } => leaf(
+                "TimeOfDay",
+                format!(
+                    "local time {} ({})",
+                    ctx.local_now().format("%H:%M"),
+                    ctx.timezone
+                ),
+                format!(
+                    "between {start_hour:02}:{start_minute:02} and {end_hour:02}:{end_minute:02}"
+                ),
+            ),
+            Condition::DayOfWeek { days } => leaf(
+                "DayOfWeek",
+                format!("local day {} ({})", ctx.local_weekday(), ctx.timezone),
+                format!("one of {days:?}"),
+            ),
+            Condition::ProductQuantity {
+                product_id,
+                operator,
+                quantity,
+            } => leaf(
+                "ProductQuantity",
+                format!("{product_id} quantity {}", ctx.cart.quantity_of(product_id)),
+                format!("{operator:?} {quantity}"),
+            ),
+            Condition::FirstPurchase => leaf(
+                "FirstPurchase",
+                format!("is_first_purchase {}", ctx.is_first_purchase),
+                "is_first_purchase true".to_string(),
+            ),
+            Condition::Coupon { code } => {
+                let observed = match &ctx.applied_coupon {
+                    Some(coupon) => format!(
+                        "coupon {} ({}; used {} of {}, {} of {} by customer, expires {})",
+                        coupon.code,
+                        coupon
+                            .check(ctx)
+                            .map_or_else(|r| format!("rejected: {r:?}"), |()| "usable".to_string()),
+                        coupon.used_count,
+                        coupon
+                            .max_uses
+                            .map_or("unlimited".to_string(), |max| max.to_string()),
+                        ctx.redemptions_of(Entity::Coupon, &coupon.id)
+                            .map_or("anonymous".to_string(), |used| used.to_string()),
+                        coupon
+                            .max_uses_per_customer
+                            .map_or("unlimited".to_string(), |max| max.to_string()),
+                        coupon
+                            .expires_at
+                            .map_or("never".to_string(), |exp| exp.to_rfc3339()),
+                    ),
+                    None => "no coupon applied".to_string(),
+                };
+                leaf("Coupon", observed, format!("valid coupon {code}"))
+            }
+            Condition::MinimumSpend { amount } => leaf(
+                "MinimumSpend",
+                format!("cart_total {}", ctx.cart_total()),
+                format!("at least {amount}"),
+            ),
+            Condition::MembershipTier { tiers } => {
+                let tiers: Vec<&str> = tiers.iter().map(|t| t.name.as_str()).collect();
+                leaf(
+                    "MembershipTier",
+                    describe_membership(ctx),
+                    format!("valid membership in {tiers:?}"),
+                )
+            }
+            Condition::Bundle { components } => {
+                let count = bundle_count(&ctx.cart, components);
+                let components: Vec<String> = components
+                    .iter()
+                    .map(|c| format!("{} x{}", c.product_id, c.quantity))
+                    .collect();
+                leaf(
+                    "Bundle",
+                    format!("{count} complete bundles"),
+                    format!("at least one bundle of {components:?}"),
+                )
+            }
+            Condition::MembershipActive => leaf(
+                "MembershipActive",
+                describe_membership(ctx),
+                "valid membership".to_string(),
+            ),
+            Condition::All { conditions } => {
+                let children: Vec<_> = conditions.iter().map(|c| c.trace(ctx)).collect();
+                combinator("All", children.iter().all(|c| c.passed), children)
+            }
+            Condition::Any { conditions } => {
+                let children: Vec<_> = conditions.iter().map(|c| c.trace(ctx)).collect();
+                combinator("Any", children.iter().any(|c| c.passed), children)
+            }
+            Condition::Not { condition } => {
+                let child = condition.trace(ctx);
+                combinator("Not", !child.passed, vec![child])
+            }
+        }
+    }
+}
+
+fn combinator(name: &str, passed: bool, children: Vec<ConditionTrace>) -> ConditionTrace {
+    ConditionTrace {
+        condition: name.to_string(),
+        passed,
+        observed: None,
+        expected: None,
+        children,
+    }
+}
+
+fn describe_timeframe(days: i32) -> String {
+    if days > 0 {
+        format!("in the last {days} days")
+    } else {
+        "ever".to_string()
+    }
+}
+
+fn describe_membership(ctx: &EvaluationContext) -> String {
+    match &ctx.customer_membership {
+        Some(membership) => format!(
+            "membership {} ({})",
+            membership.tier.name,
+            if membership.is_valid_at(ctx.now) {
+                "valid"
+            } else {
+                "not valid now"
+            }
+        ),
+        None => "no membership".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        discount::{DiscountAction, Operator},
+        fixtures::{cart, ctx, d128, line, now, rule, usd},
+    };
+
+    fn traced_rule(conditions: Vec<Condition>) -> DiscountRule {
+        let mut rule = rule(
+            "rule-1",
+            vec![DiscountAction::PercentageOff {
+                percent: d128("10"),
+            }],
+        );
+        rule.conditions = conditions;
+        rule
+    }
+
+    #[test]
+    fn test_trace_reports_every_failing_check() {
+        let mut rule = traced_rule(vec![
+            Condition::CartTotal {
+                operator: Operator::GreaterThanOrEqual,
+                value: usd("100"),
+            },
+            Condition::FirstPurchase,
+        ]);
+        rule.end_date = Some(now() - chrono::Duration::days(1));
+        rule.max_usage = Some(1);
+        rule.usage_count = 1;
+        let ctx = ctx(cart(vec![line("a", "a", "40", 1)]));
+
+        let trace = rule.evaluate_with_trace(&ctx);
+        assert!(!trace.passed);
+        assert!(trace.window.ended);
+        assert!(!trace.window.not_started);
+        assert!(trace.usage.exhausted);
+        assert_eq!(trace.usage.customer_usage_count, None);
+        let passed: Vec<bool> = trace.conditions.iter().map(|c| c.passed).collect();
+        assert_eq!(passed, vec![false, false]);
+        let cart_total = &trace.conditions[0];
+        assert_eq!(cart_total.observed.as_deref(), Some("cart_total 40 USD"));
+        assert_eq!(
+            cart_total.expected.as_deref(),
+            Some("GreaterThanOrEqual 100 USD")
+        );
+
+        let stored = bson::to_document(&trace).unwrap();
+        assert_eq!(stored.get_array("conditions").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_trace_agrees_with_evaluate() {
+        let rule = traced_rule(vec![Condition::ProductQuantity {
+            product_id: "a".to_string(),
+            operator: Operator::GreaterThanOrEqual,
+            quantity: 2,
+        }]);
+        for quantity in [1, 2] {
+            let ctx = ctx(cart(vec![line("a", "a", "10", quantity)]));
+            assert_eq!(rule.evaluate_with_trace(&ctx).passed, rule.evaluate(&ctx));
+        }
+    }
+
+    #[test]
+    fn test_trace_nests_combinator_children() {
+        let rule = traced_rule(vec![Condition::Not {
+            condition: Box::new(Condition::Any {
+                conditions: vec![
+                    Condition::FirstPurchase,
+                    Condition::CustomerGroup {
+                        group_ids: vec!["vip".to_string()],
+                    },
+                ],
+            }),
+        }]);
+        let ctx = ctx(cart(vec![line("a", "a", "10", 1)]));
+
+        let trace = rule.evaluate_with_trace(&ctx);
+        assert!(trace.passed);
+        let not = &trace.conditions[0];
+        assert_eq!(not.condition, "Not");
+        assert_eq!(not.observed, None);
+        let any = &not.children[0];
+        assert_eq!(any.condition, "Any");
+        assert!(!any.passed);
+        let children: Vec<&str> = any.children.iter().map(|c| c.condition.as_str()).collect();
+        assert_eq!(children, vec!["FirstPurchase", "CustomerGroup"]);
+    }
+}